@@ -0,0 +1,416 @@
+use anyhow::{anyhow, Result};
+use flate2::{bufread::GzDecoder, write::DeflateEncoder, Compression, Crc};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Fixed part of a gzip member header, up to and including XLEN
+const HEADER_LEN: usize = 12;
+/// CRC32 and ISIZE
+const TRAILER_LEN: usize = 8;
+/// Uncompressed bytes per written block, as in htslib, so even incompressible
+/// data fits the 64 KiB block size limit
+const MAX_BLOCK_DATA: usize = 0xff00;
+/// The empty block that ends every BGZF file
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0, 0, 0,
+    0, 0, 0,
+];
+
+/// True if `header` starts a gzip member carrying the BGZF `BC` extra field
+pub fn is_bgzf_header(header: &[u8]) -> bool {
+    header.len() >= 18
+        && header[..2] == GZIP_MAGIC
+        && header[3] & 4 != 0
+        && header[12] == b'B'
+        && header[13] == b'C'
+}
+
+pub fn is_bgzf(path: &str) -> Result<bool> {
+    let mut header = [0u8; 18];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(is_bgzf_header(&header)),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Block offsets from a `.gzi` index: (compressed offset, uncompressed offset)
+#[derive(Debug, Clone, Default)]
+pub struct GziIndex {
+    blocks: Vec<(u64, u64)>,
+}
+
+impl GziIndex {
+    pub fn path_for(bgzf_path: &str) -> String {
+        format!("{bgzf_path}.gzi")
+    }
+
+    /// Reads the index next to `bgzf_path` if there is one
+    pub fn find(bgzf_path: &str) -> Result<Option<Self>> {
+        let path = Self::path_for(bgzf_path);
+        if Path::new(&path).exists() {
+            Ok(Some(Self::read(&path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let count = read_u64(&mut reader)?;
+        // The first block is implicit in the file
+        let mut blocks = vec![(0, 0)];
+        for _ in 0..count {
+            let compressed = read_u64(&mut reader)?;
+            let uncompressed = read_u64(&mut reader)?;
+            blocks.push((compressed, uncompressed));
+        }
+        Ok(Self { blocks })
+    }
+
+    /// Indexes a BGZF file by walking its block headers, without decompressing
+    pub fn build<R: Read>(mut reader: R) -> Result<Self> {
+        let mut blocks = vec![(0, 0)];
+        let (mut compressed, mut uncompressed) = (0u64, 0u64);
+
+        while let Some(block) = read_raw_block(&mut reader)? {
+            let len = block_len(&block);
+            if compressed > 0 && len > 0 {
+                blocks.push((compressed, uncompressed));
+            }
+            compressed += block.len() as u64;
+            uncompressed += len;
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        // The first block is implicit in the file
+        let blocks = &self.blocks[1..];
+        writer.write_all(&(blocks.len() as u64).to_le_bytes())?;
+        for &(compressed, uncompressed) in blocks {
+            writer.write_all(&compressed.to_le_bytes())?;
+            writer.write_all(&uncompressed.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The last block starting at or before uncompressed offset `pos`
+    fn block_for(&self, pos: u64) -> (u64, u64) {
+        let i = self.blocks.partition_point(|&(_, uncompressed)| uncompressed <= pos);
+        self.blocks[i.saturating_sub(1)]
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads one whole compressed block, header to trailer, or None at end of file
+fn read_raw_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut block = vec![0u8; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        match reader.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated BGZF block")),
+            n => filled += n,
+        }
+    }
+    if block[..2] != GZIP_MAGIC || block[3] & 4 == 0 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Not a BGZF block"));
+    }
+    let xlen = u16::from_le_bytes([block[10], block[11]]) as usize;
+    block.resize(HEADER_LEN + xlen, 0);
+    reader.read_exact(&mut block[HEADER_LEN..])?;
+
+    // Extra subfields are SI1 SI2 SLEN(2) DATA; BGZF stores the block size minus one in BC
+    let extra = &block[HEADER_LEN..];
+    let mut i = 0;
+    let mut block_size = None;
+    while i + 4 <= extra.len() {
+        let slen = u16::from_le_bytes([extra[i + 2], extra[i + 3]]) as usize;
+        if extra[i] == b'B' && extra[i + 1] == b'C' && slen == 2 && i + 6 <= extra.len() {
+            block_size = Some(u16::from_le_bytes([extra[i + 4], extra[i + 5]]) as usize + 1);
+            break;
+        }
+        i += 4 + slen;
+    }
+    let block_size = match block_size {
+        Some(size) if size >= HEADER_LEN + xlen + TRAILER_LEN => size,
+        _ => return Err(io::Error::new(ErrorKind::InvalidData, "BGZF block without BC field")),
+    };
+
+    let header_len = block.len();
+    block.resize(block_size, 0);
+    reader.read_exact(&mut block[header_len..])?;
+    Ok(Some(block))
+}
+
+/// Uncompressed size of a block, from the ISIZE field of its trailer
+fn block_len(block: &[u8]) -> u64 {
+    let isize_bytes: [u8; 4] = block[block.len() - 4..].try_into().unwrap();
+    u32::from_le_bytes(isize_bytes) as u64
+}
+
+/// Reader over the uncompressed bytes of a BGZF file. Reads stream block by block,
+/// and seeking to an uncompressed offset jumps to the right block through a `.gzi` index.
+pub struct BgzfReader<R: Read + Seek> {
+    inner: R,
+    index: GziIndex,
+    block: Vec<u8>,
+    block_pos: usize,
+}
+
+impl BgzfReader<File> {
+    /// Opens a BGZF file with its `.gzi` index, or `None` if it has no index
+    pub fn open(path: &str) -> Result<Option<Self>> {
+        match GziIndex::find(path)? {
+            Some(index) => Ok(Some(Self::new(File::open(path)?, index))),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    pub fn new(inner: R, index: GziIndex) -> Self {
+        Self {
+            inner,
+            index,
+            block: Vec::new(),
+            block_pos: 0,
+        }
+    }
+
+    /// Decompresses the next block into `block`. Returns false at end of file.
+    fn read_block(&mut self) -> io::Result<bool> {
+        self.block.clear();
+        self.block_pos = 0;
+
+        loop {
+            let Some(compressed) = read_raw_block(&mut self.inner)? else {
+                return Ok(false);
+            };
+            // Each block is a complete gzip member, so GzDecoder also checks the CRC
+            GzDecoder::new(&compressed[..]).read_to_end(&mut self.block)?;
+            // Skip empty blocks such as the EOF marker
+            if !self.block.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.block_pos >= self.block.len() && !self.read_block()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.block.len() - self.block_pos);
+        buf[..n].copy_from_slice(&self.block[self.block_pos..self.block_pos + n]);
+        self.block_pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for BgzfReader<R> {
+    /// Only seeking from the start, to an uncompressed offset, is supported
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::Start(target) = pos else {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "BGZF readers can only seek from the start",
+            ));
+        };
+
+        let (compressed, uncompressed) = self.index.block_for(target);
+        self.inner.seek(SeekFrom::Start(compressed))?;
+        self.block.clear();
+        self.block_pos = 0;
+
+        let mut to_skip = target - uncompressed;
+        while to_skip > 0 {
+            if !self.read_block()? {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "Seek past end of BGZF file"));
+            }
+            let len = self.block.len() as u64;
+            if to_skip < len {
+                self.block_pos = to_skip as usize;
+                break;
+            }
+            to_skip -= len;
+            self.block_pos = self.block.len();
+        }
+        Ok(target)
+    }
+}
+
+/// Errors for region queries on compressed files that cannot be randomly accessed
+pub fn require_bgzf(path: &str) -> Result<()> {
+    if is_bgzf(path)? {
+        Ok(())
+    } else {
+        Err(anyhow!("{path} is gzip compressed but not with bgzip, so it cannot be indexed"))
+    }
+}
+
+/// Writes BGZF: gzip members of at most 64 KiB that `tabix` and `samtools` can index.
+/// The end-of-file block is written by `finish`, or when the writer is dropped.
+pub struct BgzfWriter<W: Write> {
+    inner: Option<W>,
+    data: Vec<u8>,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: Some(inner),
+            data: Vec::with_capacity(MAX_BLOCK_DATA),
+        }
+    }
+
+    /// Writes the pending block and the end-of-file block
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        if let Some(mut inner) = self.inner.take() {
+            inner.write_all(&EOF_BLOCK)?;
+            inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Compresses the pending data into one block
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| io::Error::other("BGZF writer is already finished"))?;
+
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(&self.data)?;
+        let compressed = deflate.finish()?;
+        let mut crc = Crc::new();
+        crc.update(&self.data);
+
+        // BC holds the total block size minus one
+        let block_size = HEADER_LEN + 6 + compressed.len() + TRAILER_LEN;
+        let bsize = u16::try_from(block_size - 1)
+            .map_err(|_| io::Error::other("BGZF block exceeds 64 KiB"))?;
+        let mut header = [0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0, 0];
+        header[16..].copy_from_slice(&bsize.to_le_bytes());
+
+        inner.write_all(&header)?;
+        inner.write_all(&compressed)?;
+        inner.write_all(&crc.sum().to_le_bytes())?;
+        inner.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        self.data.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        let n = buf.len().min(MAX_BLOCK_DATA - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Ends the current block early, so everything written so far is on disk
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    /// Fallback for writers that were not finished, such as on an early error return
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Cursor;
+
+    /// Data spanning three blocks, with a distinct byte at every offset mod 251
+    fn sample() -> Vec<u8> {
+        (0..2 * MAX_BLOCK_DATA + 1000).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut writer = BgzfWriter::new(&mut compressed);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        compressed
+    }
+
+    #[test]
+    fn writer_output_is_gzip_with_eof_block() {
+        let data = sample();
+        let compressed = compress(&data);
+        assert!(is_bgzf_header(&compressed));
+        assert!(compressed.ends_with(&EOF_BLOCK));
+
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn gzi_round_trips_and_seeks() {
+        let data = sample();
+        let compressed = compress(&data);
+        let index = GziIndex::build(&compressed[..]).unwrap();
+        let uncompressed: Vec<u64> = index.blocks.iter().map(|&(_, u)| u).collect();
+        let block = MAX_BLOCK_DATA as u64;
+        assert_eq!(uncompressed, [0, block, 2 * block]);
+
+        let path = std::env::temp_dir().join(format!("lcr-test-{}.gzi", std::process::id()));
+        let path = path.to_str().unwrap();
+        index.write(path).unwrap();
+        let read = GziIndex::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read.blocks, index.blocks);
+
+        let mut reader = BgzfReader::new(Cursor::new(compressed), read);
+        for pos in [0, 17, MAX_BLOCK_DATA - 1, MAX_BLOCK_DATA, 2 * MAX_BLOCK_DATA + 999] {
+            reader.seek(SeekFrom::Start(pos as u64)).unwrap();
+            let mut byte = [0u8];
+            reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], data[pos], "byte at {pos}");
+        }
+        assert!(reader.seek(SeekFrom::Current(1)).is_err());
+    }
+
+    #[test]
+    fn plain_gzip_is_not_bgzf() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"ACGT").unwrap();
+        let gzip = encoder.finish().unwrap();
+        assert!(!is_bgzf_header(&gzip));
+        assert!(GziIndex::build(&gzip[..]).is_err());
+    }
+}
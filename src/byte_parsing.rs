@@ -0,0 +1,320 @@
+use anyhow::{anyhow, Result};
+use memchr::memchr;
+use std::{
+    io::{self, ErrorKind, Read},
+    mem,
+};
+
+use crate::fasta_parsing::BUFF_SIZE;
+
+/// Splits a reader into lines inside one reusable buffer. Lines are returned
+/// as slices of the buffer, without their `\n` or `\r\n` ending.
+#[derive(Debug)]
+pub struct LineReader<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    line_num: usize,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(BUFF_SIZE, reader)
+    }
+
+    pub fn with_capacity(capacity: usize, reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0; capacity.max(1)],
+            start: 0,
+            end: 0,
+            eof: false,
+            line_num: 0,
+        }
+    }
+
+    /// Number of the last line returned, starting at 1
+    pub fn line_num(&self) -> usize {
+        self.line_num
+    }
+
+    pub fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            if let Some(i) = memchr(b'\n', &self.buf[self.start..self.end]) {
+                let line_start = self.start;
+                self.start += i + 1;
+                self.line_num += 1;
+                return Ok(Some(trim_cr(&self.buf[line_start..line_start + i])));
+            }
+            if self.eof {
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                // Last line without a newline
+                let line_start = self.start;
+                self.start = self.end;
+                self.line_num += 1;
+                return Ok(Some(trim_cr(&self.buf[line_start..self.end])));
+            }
+            self.fill()?;
+        }
+    }
+
+    /// Moves the partial line to the front of the buffer and reads more after it,
+    /// growing the buffer if a single line doesn't fit
+    fn fill(&mut self) -> io::Result<()> {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == self.buf.len() {
+            self.buf.resize(self.buf.len() * 2, 0);
+        }
+        let read = loop {
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(read) => break read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        };
+        if read == 0 {
+            self.eof = true;
+        }
+        self.end += read;
+        Ok(())
+    }
+}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// A fasta record borrowed from the reader's buffers
+#[derive(Debug, Clone, Copy)]
+pub struct FastaRecord<'a> {
+    pub name: &'a [u8],
+    pub sequence: &'a [u8],
+}
+
+/// A fastq record borrowed from the reader's buffers
+#[derive(Debug, Clone, Copy)]
+pub struct FastqRecord<'a> {
+    pub name: &'a [u8],
+    pub sequence: &'a [u8],
+    pub quality: &'a [u8],
+}
+
+/// Fasta parser that reuses its name and sequence buffers between records
+#[derive(Debug)]
+pub struct FastaReader<R: Read> {
+    lines: LineReader<R>,
+    name: Vec<u8>,
+    sequence: Vec<u8>,
+    /// Header of the next record, read while finishing the current one
+    next_name: Option<Vec<u8>>,
+}
+
+impl<R: Read> FastaReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: LineReader::new(reader),
+            name: Vec::new(),
+            sequence: Vec::new(),
+            next_name: None,
+        }
+    }
+
+    pub fn next_record(&mut self) -> Option<Result<FastaRecord<'_>>> {
+        match self.read_next() {
+            Ok(true) => Some(Ok(FastaRecord {
+                name: &self.name,
+                sequence: &self.sequence,
+            })),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Reads the next record and hands over its sequence buffer instead of borrowing it,
+    /// so large sequences aren't copied. The buffer is shrunk to the sequence, and the
+    /// next one starts at the same size so that it rarely has to grow.
+    pub fn next_owned(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        match self.read_next() {
+            Ok(true) => {
+                let len = self.sequence.len();
+                let mut sequence = mem::replace(&mut self.sequence, Vec::with_capacity(len));
+                sequence.shrink_to_fit();
+                Some(Ok((self.name.clone(), sequence)))
+            }
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Fills `name` and `sequence` with the next record. Returns false at end of file.
+    fn read_next(&mut self) -> Result<bool> {
+        self.sequence.clear();
+        match self.next_name.take() {
+            Some(name) => self.name = name,
+            None => loop {
+                let line = self
+                    .lines
+                    .next_line()
+                    .map_err(|err| anyhow!("Invalid line/file format: {err}"))?;
+                match line {
+                    None => return Ok(false),
+                    Some([]) => continue,
+                    Some([b'>', name @ ..]) => {
+                        self.name.clear();
+                        self.name.extend_from_slice(name);
+                        break;
+                    }
+                    Some(_) => {
+                        return Err(anyhow!(
+                            "Invalid fasta format at line {}: sequence before header",
+                            self.lines.line_num()
+                        ))
+                    }
+                }
+            },
+        }
+
+        loop {
+            let line = self
+                .lines
+                .next_line()
+                .map_err(|err| anyhow!("Invalid line/file format: {err}"))?;
+            match line {
+                None => return Ok(true),
+                Some([b'>', name @ ..]) => {
+                    let mut next_name = mem::take(&mut self.next_name).unwrap_or_default();
+                    next_name.clear();
+                    next_name.extend_from_slice(name);
+                    self.next_name = Some(next_name);
+                    return Ok(true);
+                }
+                Some(line) => self.sequence.extend_from_slice(line),
+            }
+        }
+    }
+}
+
+/// Fastq parser that validates each record and reuses its buffers between records
+#[derive(Debug)]
+pub struct FastqReader<R: Read> {
+    lines: LineReader<R>,
+    name: Vec<u8>,
+    sequence: Vec<u8>,
+    quality: Vec<u8>,
+    record_num: usize,
+}
+
+impl<R: Read> FastqReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: LineReader::new(reader),
+            name: Vec::new(),
+            sequence: Vec::new(),
+            quality: Vec::new(),
+            record_num: 0,
+        }
+    }
+
+    pub fn next_record(&mut self) -> Option<Result<FastqRecord<'_>>> {
+        match self.read_next() {
+            Ok(true) => Some(Ok(FastqRecord {
+                name: &self.name,
+                sequence: &self.sequence,
+                quality: &self.quality,
+            })),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    fn error(&self, msg: &str) -> anyhow::Error {
+        anyhow!(
+            "Invalid fastq format in record {} at line {}: {msg}",
+            self.record_num,
+            self.lines.line_num()
+        )
+    }
+
+    /// Fills the record buffers with the next record. Returns false at end of file.
+    fn read_next(&mut self) -> Result<bool> {
+        let io_error = |err: io::Error| anyhow!("Invalid line/file format: {err}");
+
+        // Blank lines are only tolerated at the end of the file
+        let mut blank_line = None;
+        let valid_header = loop {
+            match self.lines.next_line().map_err(io_error)? {
+                None => return Ok(false),
+                Some([]) => {
+                    blank_line.get_or_insert(self.lines.line_num());
+                }
+                Some([b'@', name @ ..]) => {
+                    self.name.clear();
+                    self.name.extend_from_slice(name);
+                    break true;
+                }
+                Some(_) => break false,
+            }
+        };
+        self.record_num += 1;
+        if let Some(line_num) = blank_line {
+            return Err(anyhow!(
+                "Invalid fastq format before record {} at line {line_num}: unexpected blank line",
+                self.record_num
+            ));
+        }
+        if !valid_header {
+            return Err(self.error("header must start with '@'"));
+        }
+
+        if !read_line_into(&mut self.lines, &mut self.sequence).map_err(io_error)? {
+            return Err(self.error("truncated record, missing sequence line"));
+        }
+
+        let plus_error = match self.lines.next_line().map_err(io_error)? {
+            None => Some("truncated record, missing '+' line"),
+            Some([b'+', plus_name @ ..]) if !plus_name.is_empty() && plus_name != self.name => {
+                Some("separator line does not match the header")
+            }
+            Some([b'+', ..]) => None,
+            Some(_) => Some("separator line must start with '+'"),
+        };
+        if let Some(msg) = plus_error {
+            return Err(self.error(msg));
+        }
+
+        if !read_line_into(&mut self.lines, &mut self.quality).map_err(io_error)? {
+            return Err(self.error("truncated record, missing quality line"));
+        }
+        if self.quality.len() != self.sequence.len() {
+            return Err(self.error(&format!(
+                "quality length {} does not match sequence length {}",
+                self.quality.len(),
+                self.sequence.len()
+            )));
+        }
+        if let Some(&bad) = self.quality.iter().find(|b| !(b'!'..=b'~').contains(*b)) {
+            return Err(self.error(&format!("invalid quality character {:?}", bad as char)));
+        }
+        Ok(true)
+    }
+}
+
+/// Replaces the contents of `buf` with the next line. Returns false at end of file.
+fn read_line_into<R: Read>(lines: &mut LineReader<R>, buf: &mut Vec<u8>) -> io::Result<bool> {
+    match lines.next_line()? {
+        Some(line) => {
+            buf.clear();
+            buf.extend_from_slice(line);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,168 @@
+use core::fmt;
+
+use clap::ValueEnum;
+
+use crate::{
+    fasta_parsing::SeqRecord,
+    slowdust::{merge_intervals, LCR},
+};
+
+/// Longest repeat unit tried when looking for a period.
+pub const MAX_PERIOD: usize = 500;
+/// Only the first bases of very long intervals are used to estimate the period.
+pub const MAX_CLASSIFY_LEN: usize = 20_000;
+/// Minimum fraction of bases matching the base one period earlier for a tandem repeat.
+pub const MIN_PURITY: f64 = 0.8;
+/// A shorter period is preferred if its purity is within this much of the best one.
+const PURITY_TOLERANCE: f64 = 0.05;
+
+pub const STR_MAX_PERIOD: usize = 6;
+pub const VNTR_MAX_PERIOD: usize = 100;
+/// Tandem arrays at least this long are satellites regardless of their period.
+pub const SATELLITE_MIN_LEN: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum LcrClass {
+    Homopolymer,
+    Str,
+    Vntr,
+    Satellite,
+    Cryptic,
+}
+
+impl fmt::Display for LcrClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LcrClass::Homopolymer => "homopolymer",
+            LcrClass::Str => "str",
+            LcrClass::Vntr => "vntr",
+            LcrClass::Satellite => "satellite",
+            LcrClass::Cryptic => "cryptic",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub class: LcrClass,
+    /// Period of the repeat unit, 0 if no period reached `MIN_PURITY`
+    pub period: usize,
+    pub purity: f64,
+    /// First repeat unit of the interval, empty for cryptic LCRs
+    pub motif: String,
+}
+
+impl Classification {
+    pub fn get_class(&self) -> LcrClass {
+        self.class
+    }
+    pub fn get_period(&self) -> usize {
+        self.period
+    }
+    pub fn get_purity(&self) -> f64 {
+        self.purity
+    }
+    pub fn get_motif(&self) -> &str {
+        &self.motif
+    }
+}
+
+/// Classifies each of `lcrs`, intervals of `record`. With `per_run`, intervals share the
+/// classification of the merged run that holds them, so the many overlapping intervals
+/// of `--no-merge` are classified once per run instead of once each.
+pub fn classify_intervals(lcrs: &[LCR], record: &dyn SeqRecord, per_run: bool) -> Vec<Classification> {
+    if !per_run {
+        return lcrs.iter().map(|lcr| classify_lcr(lcr, record)).collect();
+    }
+    // Runs of one record are disjoint and sorted by start
+    let runs = merge_intervals(lcrs.to_vec());
+    let classifications: Vec<Classification> =
+        runs.iter().map(|run| classify_lcr(run, record)).collect();
+    lcrs.iter()
+        .map(|lcr| {
+            let run = runs.partition_point(|run| run.get_start() <= lcr.get_start()) - 1;
+            classifications[run].clone()
+        })
+        .collect()
+}
+
+/// Classifies a merged interval of `record` by the period, length and purity
+/// of its repeat unit. Only the bases that are sampled are read.
+pub fn classify_lcr(lcr: &LCR, record: &dyn SeqRecord) -> Classification {
+    let end = lcr.end.min(record.sequence_len());
+    let start = lcr.start.min(end);
+    let sample = record.bases(start, end.min(start + MAX_CLASSIFY_LEN));
+    classify_sample(sample.as_bytes(), end - start)
+}
+
+pub fn classify_seq(seq: &[u8]) -> Classification {
+    classify_sample(&seq[..seq.len().min(MAX_CLASSIFY_LEN)], seq.len())
+}
+
+/// Classifies a sequence of `len` bases from its first bases, `sample`
+fn classify_sample(sample: &[u8], len: usize) -> Classification {
+    let sample: Vec<u8> = sample
+        .iter()
+        .map(|b| b.to_ascii_uppercase())
+        .collect();
+
+    let (period, purity) = best_period(&sample);
+
+    // A tandem repeat needs at least two full copies of its unit
+    if period == 0 || purity < MIN_PURITY || len < 2 * period {
+        return Classification {
+            class: LcrClass::Cryptic,
+            period: 0,
+            purity,
+            motif: String::new(),
+        };
+    }
+
+    let class = if period == 1 {
+        LcrClass::Homopolymer
+    } else if period <= STR_MAX_PERIOD {
+        LcrClass::Str
+    } else if period <= VNTR_MAX_PERIOD && len < SATELLITE_MIN_LEN {
+        LcrClass::Vntr
+    } else {
+        LcrClass::Satellite
+    };
+
+    Classification {
+        class,
+        period,
+        purity,
+        motif: String::from_utf8_lossy(&sample[..period]).into_owned(),
+    }
+}
+
+/// Returns the shortest period whose purity is close to the best purity
+/// found, along with that purity. Ns never count as matches.
+fn best_period(seq: &[u8]) -> (usize, f64) {
+    let max_period = MAX_PERIOD.min(seq.len() / 2);
+    let purities: Vec<f64> = (1..=max_period).map(|p| period_purity(seq, p)).collect();
+
+    let best = purities.iter().cloned().fold(0.0f64, f64::max);
+    if best == 0.0 {
+        return (0, 0.0);
+    }
+    for (i, &purity) in purities.iter().enumerate() {
+        if purity >= best - PURITY_TOLERANCE {
+            return (i + 1, purity);
+        }
+    }
+    (0, 0.0)
+}
+
+fn period_purity(seq: &[u8], period: usize) -> f64 {
+    if seq.len() <= period {
+        return 0.0;
+    }
+    let matches = seq[period..]
+        .iter()
+        .zip(seq)
+        .filter(|&(&a, &b)| a == b && a != b'N')
+        .count();
+    matches as f64 / (seq.len() - period) as f64
+}
@@ -1,16 +1,323 @@
-use clap::Parser;
+use clap::{ArgAction, Args, Parser, Subcommand};
 
+use crate::{classify::LcrClass, reads::{MaskMode, PairRule, ReadMetric, TrimEnds}, output::{Coordinates, OutputCompression, OutputFormat}, scan::{Algorithm, MergeStrategy}, score_track::{TrackFormat, TrackMode}};
+
+///Find low-complexity regions in fasta, fastq, 2bit, SAM or BAM sequences
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct DustArgs{
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short, long = "input", required = true)]
     ///Input file path
+    pub input_file: Option<String>,
+
+    #[arg(short, long = "output", required = true)]
+    ///Output file path.
+    pub output_file: Option<String>,
+
+    ///Format of the LCR output
+    #[arg(long, value_enum, default_value_t = OutputFormat::Tsv)]
+    pub output_format: OutputFormat,
+
+    ///Coordinates of TSV and JSONL output [default: zero-based]. BED output and the score track are always 0-based and GFF3 always 1-based, as their formats require
+    #[arg(long, value_enum)]
+    pub coordinates: Option<Coordinates>,
+
+    ///Append the bases of each LCR to the output. Not written to GFF3
+    #[arg(long)]
+    pub with_sequence: bool,
+
+    ///Cut appended sequences longer than this and mark them as truncated
+    #[arg(long, requires = "with_sequence")]
+    pub max_sequence_len: Option<usize>,
+
+    ///Also write a summary object after each record's LCRs. Only for jsonl output
+    #[arg(long)]
+    pub record_summary: bool,
+
+    ///Write a per-record and run-level summary to this path, as JSON if it ends in .json and TSV otherwise
+    #[arg(long)]
+    pub summary: Option<String>,
+
+    ///Compression of the LCR output and score track. Auto uses BGZF for paths ending in .gz or .bgz
+    #[arg(long, value_enum, default_value_t = OutputCompression::Auto)]
+    pub compression: OutputCompression,
+
+    ///The file path for the list of adapter seqeuences. Must be fasta format
+    #[arg(short, long, default_value_t = 1)]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    #[command(flatten)]
+    pub merge: MergeArgs,
+
+    ///Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    ///Log more detail: -v for per-record timings, -vv for everything
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    ///Only output LCRs of these classes, comma separated. Outputs every class by default
+    #[arg(long, value_delimiter = ',')]
+    pub classes: Vec<LcrClass>,
+
+    ///Only scan this region, as name:start-end (1-based, inclusive). Can be repeated
+    #[arg(long = "region")]
+    pub regions: Vec<String>,
+
+    ///Only scan the regions in this BED file
+    #[arg(long)]
+    pub regions_bed: Option<String>,
+
+    ///Also write a per-base score track to this path
+    #[arg(long)]
+    pub score_track: Option<String>,
+
+    ///Format of the score track
+    #[arg(long, value_enum, default_value_t = TrackFormat::Bedgraph)]
+    pub track_format: TrackFormat,
+
+    ///Score recorded for each base in the score track
+    #[arg(long, value_enum, default_value_t = TrackMode::End)]
+    pub track_mode: TrackMode,
+
+    ///Memory-map the input instead of streaming it. Only for uncompressed fasta. Line-wrapped sequences are read from the map one chunk at a time, so a record is never held in memory whole
+    #[arg(long)]
+    pub mmap: bool,
+}
+
+/// Scorer and scoring parameters
+#[derive(Debug, Clone, Copy, Args)]
+pub struct ScanArgs {
+    ///Scoring algorithm
+    #[arg(long, value_enum, default_value_t = Algorithm::Slowdust2)]
+    pub algorithm: Algorithm,
+
+    #[command(flatten)]
+    pub kmer: KmerArgs,
+
+    ///Longest window scored, in bases
+    #[arg(short, long, default_value_t = 5000)]
+    pub window: usize,
+}
+
+/// K-mer length and threshold of the score, shared by scanning and `explain`
+#[derive(Debug, Clone, Copy, Args)]
+pub struct KmerArgs {
+    ///K-mer length, up to 32 so a k-mer fits in 64 bits
+    #[arg(short, default_value_t = 7, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=32))]
+    pub k: usize,
+
+    ///Score threshold
+    #[arg(long, default_value_t = 0.6)]
+    pub threshold: f64,
+}
+
+/// How the good intervals of a record become the reported LCRs
+#[derive(Debug, Clone, Copy, Args)]
+pub struct MergeArgs {
+    ///How overlapping good intervals are combined
+    #[arg(long, value_enum, default_value_t = MergeStrategy::Union, conflicts_with = "no_merge")]
+    pub merge: MergeStrategy,
+
+    ///Output every good interval with its score instead of merging overlapping ones
+    #[arg(long)]
+    pub no_merge: bool,
+
+    ///Only keep intervals not contained in a higher-scoring interval
+    #[arg(long, requires = "no_merge")]
+    pub maximal_only: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    ///Show the score trajectory and each scorer's verdict for one sequence
+    Explain(ExplainArgs),
+    ///Write a samtools-compatible .fai index, or print regions of an indexed fasta
+    Faidx(FaidxArgs),
+    ///Mask the low-complexity bases of fastq reads
+    MaskReads(MaskReadsArgs),
+    ///Split reads into pass and fail files by how low-complexity they are
+    FilterReads(FilterReadsArgs),
+    ///Trim LCRs such as poly-A or poly-G tails from the ends of fastq reads
+    TrimReads(TrimReadsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct FilterReadsArgs {
+    ///Reads to filter, as fastq, SAM or BAM
+    #[arg(short, long = "input")]
+    pub input_file: String,
+
+    ///Fastq output for reads below the cutoff. Paired input is written interleaved unless --pass2 is given
+    #[arg(long = "pass")]
+    pub pass_file: String,
+
+    ///Fastq output for the other reads. They are dropped if not given
+    #[arg(long = "fail")]
+    pub fail_file: Option<String>,
+
+    ///Passing second mates
+    #[arg(long = "pass2", requires = "paired")]
+    pub pass_file2: Option<String>,
+
+    ///Failing second mates
+    #[arg(long = "fail2", requires_all = ["paired", "fail_file"])]
+    pub fail_file2: Option<String>,
+
+    #[command(flatten)]
+    pub pairs: PairArgs,
+
+    ///Which mates decide whether a pair fails
+    #[arg(long, value_enum, default_value_t = PairRule::Either)]
+    pub pair_rule: PairRule,
+
+    #[arg(short, long, default_value_t = 1)]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    ///What is compared with the cutoff
+    #[arg(long, value_enum, default_value_t = ReadMetric::Fraction)]
+    pub metric: ReadMetric,
+
+    ///Reads whose metric is below this pass
+    #[arg(long)]
+    pub cutoff: f64,
+
+    ///Compression of the outputs. Auto uses BGZF for paths ending in .gz or .bgz
+    #[arg(long, value_enum, default_value_t = OutputCompression::Auto)]
+    pub compression: OutputCompression,
+}
+
+/// Paired-end input for the read modes
+#[derive(Debug, Clone, Args)]
+#[group(id = "paired", multiple = false)]
+pub struct PairArgs {
+    ///Second mates, in the same order as the first ones in --input
+    #[arg(long = "input2")]
+    pub input_file2: Option<String>,
+
+    ///The input holds both mates of each pair, one after the other
+    #[arg(long)]
+    pub interleaved: bool,
+}
+
+impl PairArgs {
+    pub fn is_paired(&self) -> bool {
+        self.input_file2.is_some() || self.interleaved
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct MaskReadsArgs {
+    ///Reads to mask, as fastq, SAM or BAM
+    #[arg(short, long = "input")]
     pub input_file: String,
 
+    ///Masked fastq output path. Paired input is written interleaved unless --output2 is given
     #[arg(short, long = "output")]
-    ///Output file path.
     pub output_file: String,
 
-    ///The file path for the list of adapter seqeuences. Must be fasta format
+    ///Masked fastq output for the second mates
+    #[arg(long = "output2", requires = "paired")]
+    pub output_file2: Option<String>,
+
+    #[command(flatten)]
+    pub pairs: PairArgs,
+
     #[arg(short, long, default_value_t = 1)]
     pub threads: usize,
-}
\ No newline at end of file
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    ///How masked bases are written
+    #[arg(long, value_enum, default_value_t = MaskMode::Lower)]
+    pub mask: MaskMode,
+
+    ///Set the quality of masked bases to this Phred score. Qualities are kept by default
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=93))]
+    pub masked_quality: Option<u8>,
+
+    ///Compression of the output. Auto uses BGZF for paths ending in .gz or .bgz
+    #[arg(long, value_enum, default_value_t = OutputCompression::Auto)]
+    pub compression: OutputCompression,
+}
+
+#[derive(Debug, Args)]
+pub struct TrimReadsArgs {
+    ///Reads to trim, as fastq, SAM or BAM
+    #[arg(short, long = "input")]
+    pub input_file: String,
+
+    ///Trimmed fastq output path. Paired input is written interleaved unless --output2 is given
+    #[arg(short, long = "output")]
+    pub output_file: String,
+
+    ///Trimmed fastq output for the second mates
+    #[arg(long = "output2", requires = "paired")]
+    pub output_file2: Option<String>,
+
+    ///TSV of the bases trimmed from each read
+    #[arg(long = "report")]
+    pub report_file: Option<String>,
+
+    #[command(flatten)]
+    pub pairs: PairArgs,
+
+    #[arg(short, long, default_value_t = 1)]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    ///Which read ends are trimmed
+    #[arg(long, value_enum, default_value_t = TrimEnds::Both)]
+    pub ends: TrimEnds,
+
+    ///Shortest LCR trimmed from an end, in bases
+    #[arg(long, default_value_t = 10)]
+    pub min_trim: usize,
+
+    ///Compression of the outputs. Auto uses BGZF for paths ending in .gz or .bgz
+    #[arg(long, value_enum, default_value_t = OutputCompression::Auto)]
+    pub compression: OutputCompression,
+}
+
+#[derive(Debug, Args)]
+pub struct FaidxArgs {
+    ///Fasta file to index
+    pub input_file: String,
+
+    ///Regions to print, as name:start-end (1-based, inclusive)
+    pub regions: Vec<String>,
+
+    ///Index output path. Defaults to the input path with .fai appended
+    #[arg(short, long = "output")]
+    pub output_file: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExplainArgs {
+    ///Sequence to explain, or a name:start-end region (1-based, inclusive) when --fasta is given
+    pub sequence: String,
+
+    ///Fasta file to read the region from
+    #[arg(short, long)]
+    pub fasta: Option<String>,
+
+    #[command(flatten)]
+    pub kmer: KmerArgs,
+
+    ///Number of top contributing k-mers to print
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use rustc_hash::FxHashMap;
+use statrs::function::factorial::ln_factorial;
+use std::io::{self, Write};
+
+use crate::{
+    command_line::{ExplainArgs, KmerArgs},
+    fasterdust,
+    region::{fetch_region, Region},
+    slowdust::{self, longdust_score},
+    slowdust2::is_good_seq,
+};
+
+/// Prints how each scorer treats a single sequence, for debugging surprising calls
+pub fn explain(args: &ExplainArgs) -> Result<()> {
+    let (label, seq) = match &args.fasta {
+        Some(path) => {
+            let region = Region::parse(&args.sequence)?;
+            let seq = fetch_region(path, &region)?;
+            (region.to_string(), seq)
+        }
+        None => ("input".to_owned(), args.sequence.clone()),
+    };
+    let KmerArgs { k, threshold } = args.kmer;
+    // K-mers are sliced by byte, which only lines up with characters in ASCII
+    if !seq.is_ascii() {
+        return Err(anyhow!("Sequence {label} contains non-ASCII characters"));
+    }
+    if seq.len() < k {
+        return Err(anyhow!("Sequence {label} is shorter than k = {k}"));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    write_explanation(&mut out, &label, &seq, k, threshold, args.top)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_explanation<W: Write>(
+    out: &mut W,
+    label: &str,
+    seq: &str,
+    k: usize,
+    t: f64,
+    top: usize,
+) -> Result<()> {
+    writeln!(out, "# {label}: {} bp, k = {k}, threshold = {t}", seq.len())?;
+
+    // Score of every prefix, adding one k-mer at a time like slowdust2
+    writeln!(out, "\n## Score trajectory")?;
+    writeln!(out, "End\tKmer\tCount\tScore")?;
+    let mut kmer_counts: FxHashMap<&str, u64> = FxHashMap::default();
+    let mut score = 0.0;
+    let mut prefix_max = (f64::NEG_INFINITY, 0);
+    for end in k..=seq.len() {
+        let kmer = &seq[end - k..end];
+        let count = kmer_counts.entry(kmer).or_insert(0);
+        *count += 1;
+        score += (*count as f64).ln() - t;
+        writeln!(out, "{end}\t{kmer}\t{count}\t{score:.6}")?;
+        if end < seq.len() && score > prefix_max.0 {
+            prefix_max = (score, end);
+        }
+    }
+
+    let mut suffix_counts: FxHashMap<&str, u64> = FxHashMap::default();
+    let mut suffix_score = 0.0;
+    let mut suffix_max = (f64::NEG_INFINITY, 0);
+    for start in (0..=seq.len() - k).rev() {
+        let count = suffix_counts.entry(&seq[start..start + k]).or_insert(0);
+        *count += 1;
+        suffix_score += (*count as f64).ln() - t;
+        if start > 0 && suffix_score > suffix_max.0 {
+            suffix_max = (suffix_score, start);
+        }
+    }
+
+    let exact_score = longdust_score(seq, k, t);
+    writeln!(out, "\n## Maxima")?;
+    writeln!(out, "Incremental score:\t{score:.6}")?;
+    writeln!(out, "Longdust score:\t{exact_score:.6}")?;
+    if prefix_max.1 > 0 {
+        writeln!(out, "Max proper prefix:\t{:.6}\t(end {})", prefix_max.0, prefix_max.1)?;
+    } else {
+        writeln!(out, "Max proper prefix:\tnone")?;
+    }
+    if suffix_max.1 > 0 {
+        writeln!(out, "Max proper suffix:\t{:.6}\t(start {})", suffix_max.0, suffix_max.1)?;
+    } else {
+        writeln!(out, "Max proper suffix:\tnone")?;
+    }
+
+    writeln!(out, "\n## Verdicts")?;
+    let verdict = |score: f64, is_good: bool| {
+        if score < t {
+            "Not good (below threshold)"
+        } else if is_good {
+            "Good"
+        } else {
+            "Not good"
+        }
+    };
+    writeln!(
+        out,
+        "slowdust:\t{}",
+        verdict(exact_score, slowdust::is_good_window(seq, k, t, exact_score))
+    )?;
+    writeln!(out, "slowdust2:\t{}", verdict(score, is_good_seq(seq, score, k, t)))?;
+    writeln!(
+        out,
+        "fasterdust:\t{}",
+        verdict(score, fasterdust::is_good_sequence(seq, k, t))
+    )?;
+    writeln!(
+        out,
+        "slowdust2 with slowdust score:\t{}",
+        verdict(exact_score, is_good_seq(seq, exact_score, k, t))
+    )?;
+
+    // Each occurrence of a k-mer adds ln(count) - t, so a k-mer seen c times adds ln(c!) - c*t
+    writeln!(out, "\n## Top k-mers")?;
+    writeln!(out, "Kmer\tCount\tLnFactorial\tContribution")?;
+    let mut kmers: Vec<(&str, u64)> = kmer_counts.into_iter().collect();
+    kmers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (kmer, count) in kmers.into_iter().take(top) {
+        let ln_fact = ln_factorial(count);
+        let contribution = ln_fact - t * count as f64;
+        writeln!(out, "{kmer}\t{count}\t{ln_fact:.6}\t{contribution:.6}")?;
+    }
+    Ok(())
+}
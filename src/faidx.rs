@@ -0,0 +1,409 @@
+use anyhow::{anyhow, Result};
+use rustc_hash::FxHashMap;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    bgzf::{require_bgzf, BgzfReader, GziIndex},
+    command_line::FaidxArgs,
+    fasta_parsing::{Fasta, BUFF_SIZE},
+    input::{is_gzip, open_input, ReadSeek},
+    region::Region,
+};
+
+/// Bases per line when printing fetched regions, as samtools does
+const FASTA_LINE_WIDTH: usize = 60;
+
+/// Writes the `.fai` index of a fasta file, or prints regions of it like `samtools faidx`
+pub fn faidx(args: &FaidxArgs) -> Result<()> {
+    if args.regions.is_empty() {
+        let index = FaiIndex::build_for(&args.input_file)?;
+        let path = args
+            .output_file
+            .clone()
+            .unwrap_or_else(|| FaiIndex::path_for(&args.input_file));
+        return index.write(&path);
+    }
+
+    let mut fasta = IndexedFasta::open_or_build(&args.input_file)?;
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for region in &args.regions {
+        let region = Region::parse(region)?;
+        let seq = fasta.fetch_region(&region)?;
+        writeln!(out, ">{region}")?;
+        for line in seq.as_bytes().chunks(FASTA_LINE_WIDTH) {
+            out.write_all(line)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// One line of a samtools-style `.fai` index
+#[derive(Debug, Clone)]
+pub struct FaiEntry {
+    pub name: String,
+    pub length: usize,
+    /// Byte offset of the first base
+    pub offset: u64,
+    pub line_bases: usize,
+    /// Bytes per line, including the line terminator
+    pub line_width: usize,
+}
+
+impl FaiEntry {
+    /// Byte offset of the base at 0-based position `pos`
+    pub fn base_offset(&self, pos: usize) -> u64 {
+        self.offset
+            + (pos / self.line_bases * self.line_width + pos % self.line_bases) as u64
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FaiIndex {
+    entries: Vec<FaiEntry>,
+    by_name: FxHashMap<String, usize>,
+}
+
+impl FaiIndex {
+    /// Path of the index samtools would use for `fasta_path`
+    pub fn path_for(fasta_path: &str) -> String {
+        format!("{fasta_path}.fai")
+    }
+
+    /// Reads the index next to `fasta_path` if there is one
+    pub fn find(fasta_path: &str) -> Result<Option<Self>> {
+        let path = Self::path_for(fasta_path);
+        if Path::new(&path).exists() {
+            Ok(Some(Self::read(&path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut index = Self::default();
+
+        for (line_num, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 5 {
+                return Err(anyhow!("Invalid fai format at {path}:{}", line_num + 1));
+            }
+            let parse = |field: &str| -> Result<u64> {
+                field
+                    .parse::<u64>()
+                    .map_err(|err| anyhow!("Invalid fai format at {path}:{}: {err}", line_num + 1))
+            };
+            index.push(FaiEntry {
+                name: fields[0].to_owned(),
+                length: parse(fields[1])? as usize,
+                offset: parse(fields[2])?,
+                line_bases: parse(fields[3])? as usize,
+                line_width: parse(fields[4])? as usize,
+            });
+        }
+        Ok(index)
+    }
+
+    /// Indexes a fasta file in one pass. Like samtools, every sequence line of a
+    /// record except the last must have the same length.
+    pub fn build<R: BufRead>(mut reader: R) -> Result<Self> {
+        let mut index = Self::default();
+        let mut line = Vec::new();
+        let mut pos: u64 = 0;
+        let mut line_num = 0;
+        let mut current: Option<FaiEntry> = None;
+        // Set once a record has a line shorter than its first line
+        let mut short_line = false;
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            line_num += 1;
+            let line_start = pos;
+            pos += read as u64;
+
+            let mut content = &line[..];
+            if let Some(stripped) = content.strip_suffix(b"\n") {
+                content = stripped;
+            }
+            if let Some(stripped) = content.strip_suffix(b"\r") {
+                content = stripped;
+            }
+
+            if let Some(header) = content.strip_prefix(b">") {
+                if let Some(entry) = current.take() {
+                    index.push(entry);
+                }
+                let header = String::from_utf8_lossy(header);
+                let name = header.split_whitespace().next().unwrap_or_default().to_owned();
+                if index.get(&name).is_some() {
+                    return Err(anyhow!("Duplicate sequence name {name} at line {line_num}"));
+                }
+                current = Some(FaiEntry {
+                    name,
+                    length: 0,
+                    offset: pos,
+                    line_bases: 0,
+                    line_width: 0,
+                });
+                short_line = false;
+                continue;
+            }
+
+            let Some(entry) = current.as_mut() else {
+                if content.is_empty() {
+                    continue;
+                }
+                return Err(anyhow!(
+                    "Invalid fasta format at line {line_num}: sequence before header"
+                ));
+            };
+            if content.is_empty() {
+                // Blank lines are only allowed after the last sequence line of a record
+                short_line = true;
+                continue;
+            }
+            if entry.line_bases == 0 {
+                entry.offset = line_start;
+                entry.line_bases = content.len();
+                entry.line_width = read;
+            } else if short_line || content.len() > entry.line_bases {
+                return Err(anyhow!(
+                    "Different line length in sequence {} at line {line_num}",
+                    entry.name
+                ));
+            } else if content.len() < entry.line_bases || read < entry.line_width {
+                short_line = true;
+            }
+            entry.length += content.len();
+        }
+        if let Some(entry) = current {
+            index.push(entry);
+        }
+        Ok(index)
+    }
+
+    /// Indexes the fasta at `fasta_path`. Offsets of BGZF files are uncompressed
+    /// offsets, as with samtools, and their `.gzi` block index is written too.
+    pub fn build_for(fasta_path: &str) -> Result<Self> {
+        if is_gzip(fasta_path)? {
+            require_bgzf(fasta_path)?;
+            let raw = BufReader::with_capacity(BUFF_SIZE, File::open(fasta_path)?);
+            let gzi = GziIndex::build(raw)?;
+            gzi.write(&GziIndex::path_for(fasta_path))?;
+        }
+        let reader = BufReader::with_capacity(BUFF_SIZE, open_input(fasta_path)?);
+        Self::build(reader)
+    }
+
+    /// Builds the index of the fasta at `fasta_path` and writes it next to it
+    pub fn build_and_write(fasta_path: &str) -> Result<Self> {
+        let index = Self::build_for(fasta_path)?;
+        index.write(&Self::path_for(fasta_path))?;
+        Ok(index)
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in &self.entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                entry.name, entry.length, entry.offset, entry.line_bases, entry.line_width
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, entry: FaiEntry) {
+        self.by_name.insert(entry.name.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FaiEntry> {
+        self.by_name.get(name).map(|&i| &self.entries[i])
+    }
+
+    pub fn entries(&self) -> &[FaiEntry] {
+        &self.entries
+    }
+
+    /// Reads bases `start..end` (0-based half-open) of record `name`.
+    /// `end` is clamped to the record length.
+    pub fn fetch<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        name: &str,
+        start: usize,
+        end: usize,
+    ) -> Result<String> {
+        let entry = self
+            .get(name)
+            .ok_or_else(|| anyhow!("Sequence {name} not found in fai index"))?;
+        let end = end.min(entry.length);
+        if start >= end {
+            return Ok(String::new());
+        }
+        if entry.line_bases == 0 {
+            return Err(anyhow!("Invalid fai entry for {name}: zero bases per line"));
+        }
+
+        let from = entry.base_offset(start);
+        let to = entry.base_offset(end - 1) + 1;
+        let mut bytes = vec![0u8; (to - from) as usize];
+        reader.seek(SeekFrom::Start(from))?;
+        reader.read_exact(&mut bytes)?;
+
+        bytes.retain(|&b| b != b'\n' && b != b'\r');
+        String::from_utf8(bytes).map_err(|err| anyhow!("Invalid sequence in {name}: {err}"))
+    }
+}
+
+/// A fasta file with a `.fai` index, for fetching records and regions by name
+pub struct IndexedFasta<R: Read + Seek> {
+    reader: R,
+    index: FaiIndex,
+}
+
+impl IndexedFasta<Box<dyn ReadSeek>> {
+    /// Opens `path` with its existing `.fai` index, or `None` if it has no index.
+    /// BGZF files also need their `.gzi` index.
+    pub fn open(path: &str) -> Result<Option<Self>> {
+        match FaiIndex::find(path)? {
+            Some(index) => Ok(Some(Self::new(Self::open_reader(path)?, index))),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens `path`, building and writing its indexes first if they are missing
+    pub fn open_or_build(path: &str) -> Result<Self> {
+        let index = match FaiIndex::find(path)? {
+            Some(index) if !is_gzip(path)? || GziIndex::find(path)?.is_some() => index,
+            _ => FaiIndex::build_and_write(path)?,
+        };
+        Ok(Self::new(Self::open_reader(path)?, index))
+    }
+
+    fn open_reader(path: &str) -> Result<Box<dyn ReadSeek>> {
+        if !is_gzip(path)? {
+            return Ok(Box::new(File::open(path)?));
+        }
+        require_bgzf(path)?;
+        match BgzfReader::open(path)? {
+            Some(reader) => Ok(Box::new(reader)),
+            None => Err(anyhow!(
+                "{path} has a .fai index but no .gzi index, run lcr faidx to create it"
+            )),
+        }
+    }
+}
+
+impl<R: Read + Seek> IndexedFasta<R> {
+    pub fn new(reader: R, index: FaiIndex) -> Self {
+        Self { reader, index }
+    }
+
+    pub fn get_index(&self) -> &FaiIndex {
+        &self.index
+    }
+
+    /// Reads bases `start..end` (0-based half-open) of record `name`
+    pub fn fetch(&mut self, name: &str, start: usize, end: usize) -> Result<String> {
+        self.index.fetch(&mut self.reader, name, start, end)
+    }
+
+    pub fn fetch_record(&mut self, name: &str) -> Result<Fasta> {
+        let length = self
+            .index
+            .get(name)
+            .ok_or_else(|| anyhow!("Sequence {name} not found in fai index"))?
+            .length;
+        let seq = self.fetch(name, 0, length)?;
+        Ok(Fasta::new(name.to_owned(), seq))
+    }
+
+    /// Reads a region, checking that it lies within its record
+    pub fn fetch_region(&mut self, region: &Region) -> Result<String> {
+        let entry = self
+            .index
+            .get(region.get_name())
+            .ok_or_else(|| anyhow!("Sequence {} not found in fai index", region.get_name()))?;
+        let end = region.end_within(entry.length);
+        if region.get_start() >= end {
+            return Err(anyhow!(
+                "Region {region} is outside of {} ({} bp)",
+                entry.name,
+                entry.length
+            ));
+        }
+        self.fetch(region.get_name(), region.get_start(), end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const FASTA: &str = ">chr1 first\nACGTA\nCGTAC\nGT\n>chr2\nTTTT\n";
+
+    #[test]
+    fn build_matches_samtools_layout() {
+        let index = FaiIndex::build(Cursor::new(FASTA)).unwrap();
+        let chr1 = index.get("chr1").unwrap();
+        assert_eq!((chr1.length, chr1.offset, chr1.line_bases, chr1.line_width), (12, 12, 5, 6));
+        let chr2 = index.get("chr2").unwrap();
+        assert_eq!((chr2.length, chr2.offset, chr2.line_bases, chr2.line_width), (4, 33, 4, 5));
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let index = FaiIndex::build(Cursor::new(FASTA)).unwrap();
+        let path = std::env::temp_dir().join(format!("lcr-test-{}.fai", std::process::id()));
+        let path = path.to_str().unwrap();
+        index.write(path).unwrap();
+        let read = FaiIndex::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let fields = |index: &FaiIndex| {
+            index
+                .entries()
+                .iter()
+                .map(|e| (e.name.clone(), e.length, e.offset, e.line_bases, e.line_width))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fields(&read), fields(&index));
+    }
+
+    #[test]
+    fn fetch_spans_line_breaks() {
+        let index = FaiIndex::build(Cursor::new(FASTA)).unwrap();
+        let mut reader = Cursor::new(FASTA);
+        assert_eq!(index.fetch(&mut reader, "chr1", 3, 11).unwrap(), "TACGTACG");
+        assert_eq!(index.fetch(&mut reader, "chr1", 10, 100).unwrap(), "GT");
+        assert_eq!(index.fetch(&mut reader, "chr2", 0, 4).unwrap(), "TTTT");
+        assert!(index.fetch(&mut reader, "chr3", 0, 1).is_err());
+    }
+
+    #[test]
+    fn build_rejects_uneven_lines() {
+        assert!(FaiIndex::build(Cursor::new(">a\nACG\nACGT\n")).is_err());
+        assert!(FaiIndex::build(Cursor::new(">a\nACGT\nAC\nACGT\n")).is_err());
+        assert!(FaiIndex::build(Cursor::new(">a\nAC\n>a\nAC\n")).is_err());
+    }
+}
@@ -1,11 +1,34 @@
 use anyhow::{anyhow, Ok, Result};
 use std::{
-    io::{BufRead, BufReader, Lines, Read},
-    mem,
+    borrow::Cow,
+    io::Read,
 };
 
+use crate::byte_parsing::{FastaReader, FastqReader};
+
 pub const BUFF_SIZE: usize = 1 << 20;
 
+/// A named sequence the scorers can read, whether it is owned or mapped from a file
+pub trait SeqRecord {
+    fn get_name(&self) -> &str;
+    fn get_sequence(&self) -> &str;
+
+    fn sequence_len(&self) -> usize {
+        self.get_sequence().len()
+    }
+
+    /// Bases `start..end` of the sequence, borrowed when they are stored in one piece
+    fn bases(&self, start: usize, end: usize) -> Cow<'_, str> {
+        Cow::Borrowed(&self.get_sequence()[start..end])
+    }
+
+    /// Whether `get_sequence` is free. Records that would have to build the whole
+    /// sequence are scanned a chunk at a time through `bases` instead.
+    fn is_contiguous(&self) -> bool {
+        true
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Fasta {
     pub name: String,
@@ -52,75 +75,69 @@ impl Fasta {
     }
 }
 
+impl SeqRecord for Fasta {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+    fn get_sequence(&self) -> &str {
+        &self.sequence
+    }
+}
+
+impl SeqRecord for Fastq {
+    fn get_name(&self) -> &str {
+        &self.id
+    }
+    fn get_sequence(&self) -> &str {
+        &self.sequence
+    }
+}
+
+/// Owned records on top of `FastaReader`. The reader is buffered by the parser,
+/// so it should not be wrapped in a `BufReader`.
 #[derive(Debug)]
 pub struct FastaIterator<T: Read> {
-    lines_reader: Lines<BufReader<T>>,
-    curr_name: String,
+    reader: FastaReader<T>,
 }
 
+/// Owned records on top of `FastqReader`, which buffers the reader itself
 pub struct FastqIterator<T:Read> {
-    lines_reader: Lines<BufReader<T>>,
-    curr_name: String,
-    counter: i32,
+    reader: FastqReader<T>,
+    /// Set after an error, since the reader can't resync to the next record
+    failed: bool,
 }
 
 impl<T: Read> FastqIterator<T> {
-    pub fn new(bufreader: BufReader<T>) -> Self {
+    pub fn new(reader: T) -> Self {
         Self {
-            lines_reader: bufreader.lines(),
-            curr_name: String::new(),
-            counter: 0,
+            reader: FastqReader::new(reader),
+            failed: false,
         }
     }
+
+    /// Drops the qualities, for callers that only need the sequences
+    pub fn into_fasta(self) -> impl Iterator<Item = Result<Fasta>> {
+        self.map(|record| record.map(Fastq::to_fasta))
+    }
 }
 
 impl<T: Read> Iterator for FastqIterator<T>{
-    type Item = Result<Fasta>;
+    type Item = Result<Fastq>;
 
     fn next(&mut self) -> Option<Self::Item>{
-        let mut seq: String = String::new();
-
-        for line in self.lines_reader.by_ref(){
-            let line = match line{
-                Result::Ok(line) => line,
-                Err(err) => return Some(Err(anyhow!("Invalid line/file format: {err}")))
-            };
-
-            if self.counter == 0 && line.starts_with('@'){
-                self.counter += 1;
-                let mut name: String = line.strip_prefix('@').unwrap().to_owned();
-                if seq.is_empty(){
-                    name.clone_into(&mut self.curr_name);
-                    continue;
-                }
-                
-                mem::swap(&mut name, &mut self.curr_name);
-                return Some(Ok(Fasta {
-                    name, 
-                    sequence: seq
-                }))
-            } else if self.counter == 1{
-                seq = line;
-                self.counter += 1;
-                continue
-            } else if self.counter == 2{
-                self.counter += 1;
-                continue
-            } else if self.counter == 3{
-                self.counter = 0;
-                continue
-            } else {
-                return Some(Err(anyhow!("Invalid fastq format")));
-            }
-        }
-        if seq.is_empty() {
-            None
-        } else {
-            Some(Ok(Fasta {
-                name: mem::take(&mut self.curr_name),
-                sequence: seq,
-            }))
+        if self.failed {
+            return None;
         }
+        let record = self.reader.next_record().map(|record| {
+            let record = record?;
+            Ok(Fastq::new(
+                to_string(record.name)?,
+                to_string(record.sequence)?,
+                to_string(record.quality)?,
+            ))
+        });
+        self.failed = matches!(record, Some(Err(_)));
+        record
     }
 }
 
@@ -128,48 +145,95 @@ impl<T: Read> Iterator for FastaIterator<T> {
     type Item = Result<Fasta>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut seq: String = String::new();
-
-        for line in self.lines_reader.by_ref() {
-            let line = match line {
-                Result::Ok(line) => line,
-                Err(err) => return Some(Err(anyhow!("Invalid line/file format: {err}"))),
+        loop {
+            let (name, sequence) = match self.reader.next_owned()? {
+                Result::Ok(record) => record,
+                Err(err) => return Some(Err(err)),
             };
-
-            if line.starts_with('>') {
-                let mut name = line.strip_prefix('>').unwrap().to_owned();
-                if seq.is_empty() {
-                    name.clone_into(&mut self.curr_name);
-                    continue;
-                }
-                mem::swap(&mut name, &mut self.curr_name);
-                return Some(Ok(Fasta {
-                    name,
-                    sequence: seq,
-                }));
-            //Checks for valid starting line in fasta
-            } else if !self.curr_name.is_empty() {
-                seq += &line;
-            } else {
-                return Some(Err(anyhow!("Invalid fasta format")));
+            // Records without sequence are skipped
+            if sequence.is_empty() {
+                continue;
             }
-        }
-        if seq.is_empty() {
-            None
-        } else {
-            Some(Ok(Fasta {
-                name: mem::take(&mut self.curr_name),
-                sequence: seq,
-            }))
+            return Some(String::from_utf8(sequence)
+                .map_err(|err| anyhow!("Invalid sequence encoding: {err}"))
+                .and_then(|sequence| Ok(Fasta::new(to_string(&name)?, sequence))));
         }
     }
 }
 
 impl<T: Read> FastaIterator<T> {
-    pub fn new(bufreader: BufReader<T>) -> Self {
+    pub fn new(reader: T) -> Self {
         Self {
-            lines_reader: bufreader.lines(),
-            curr_name: String::new()
+            reader: FastaReader::new(reader),
+        }
+    }
+}
+
+fn to_string(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|err| anyhow!("Invalid text encoding: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(fastq: &str) -> Vec<Result<Fastq>> {
+        FastqIterator::new(fastq.as_bytes()).collect()
+    }
+
+    fn error_of(fastq: &str) -> String {
+        let records = parse(fastq);
+        match records.last() {
+            Some(Err(err)) => err.to_string(),
+            _ => panic!("{fastq:?} was accepted"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_valid_records() {
+        let records = parse("@r1 x\nACGT\n+\nIIII\n@r2\nAC\n+r2\n#I\n");
+        let records: Vec<Fastq> = records.into_iter().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get_id(), "r1 x");
+        assert_eq!(records[0].get_sequence(), "ACGT");
+        assert_eq!(records[1].get_quality(), "#I");
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        let cases = [
+            ("r1\nACGT\n+\nIIII\n", "header must start with '@'"),
+            ("@r1\nACGT\n+\n", "missing quality line"),
+            ("@r1\nACGT\n", "missing '+' line"),
+            ("@r1\nACGT\n-\nIIII\n", "separator line must start with '+'"),
+            ("@r1\nACGT\n+r2\nIIII\n", "separator line does not match"),
+            ("@r1\nACGT\n+\nIII\n", "quality length 3 does not match sequence length 4"),
+            ("@r1\nACGT\n+\nII I\n", "invalid quality character"),
+            ("@r1\nA\n+\nI\n\n@r2\nA\n+\nI\n", "unexpected blank line"),
+        ];
+        for (fastq, msg) in cases {
+            let err = error_of(fastq);
+            assert!(err.contains(msg), "{fastq:?} gave {err:?}, expected {msg:?}");
+        }
+    }
+
+    #[test]
+    fn reports_the_failing_record() {
+        let err = error_of("@r1\nA\n+\nI\n@r2\nAC\n+\nI\n");
+        assert!(err.contains("record 2 at line 8"), "{err}");
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let records = parse("@r1\nA\n+\nII\n@r2\nA\n+\nI\n");
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_err());
+    }
+
+    #[test]
+    fn allows_trailing_blank_lines() {
+        let records = parse("@r1\nA\n+\nI\n\n\n");
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_ok());
+    }
+}
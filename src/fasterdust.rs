@@ -1,5 +1,5 @@
 use rustc_hash::FxHashMap;
-use crate::fasta_parsing::Fasta;
+use crate::fasta_parsing::SeqRecord;
 use crate::slowdust::LCR;
 
 /// Encode A/C/G/T → 2-bit; anything else -> None
@@ -47,7 +47,7 @@ fn precompute_kmers(seq: &[u8], k: usize) -> Vec<Option<u64>> {
 /// k: k-mer length (set 7 to match your scoring)
 /// t: threshold T (used in S_L as the per-k-mer penalty, AND as the minimum score filter)
 pub fn fasterdust(
-    input: &Fasta,
+    input: &dyn SeqRecord,
     k: usize,
     max_window: usize,
     t: f64,
@@ -69,10 +69,7 @@ pub fn fasterdust(
 
     // Precompute ln(n) for increments Δ = ln(c_prev+1) - t
     let max_kmers_per_window = max_window.saturating_sub(k).saturating_add(1);
-    let mut ln_table = vec![0.0f64; max_kmers_per_window + 2]; // index by (c_prev+1)
-    for n in 1..ln_table.len() {
-        ln_table[n] = (n as f64).ln();
-    }
+    let ln_table = ln_table(max_kmers_per_window + 2); // index by (c_prev+1)
 
     for end in 0..seq.len() {
         // We can only form windows with at least one k-mer if end+1 >= k
@@ -86,7 +83,6 @@ pub fn fasterdust(
 
         let mut win_counts: FxHashMap<u64, u32> = FxHashMap::default();
         let mut s_total = 0.0f64;  // S_L(window)
-        let mut ell = 0usize;      // # of k-mers in window
 
         // Start from the smallest window with >=1 k-mer, and grow leftward
         // Each iteration adds the k-mer starting at `start`
@@ -106,7 +102,6 @@ pub fn fasterdust(
             let entry = win_counts.entry(code).or_insert(0);
             let c_prev = *entry as usize;
             *entry += 1;
-            ell += 1;
             if c_prev + 1 < ln_table.len() {
                 s_total += ln_table[c_prev + 1] - t;
             } else {
@@ -117,11 +112,12 @@ pub fn fasterdust(
             // Only evaluate "good" if the total score passes your minimum filter
             if s_total >= t
                 && is_good_window(&kmers, s, end, k, t, &ln_table, s_total) {
-                    // Push [start, end] inclusive, with exact sequence slice
+                    // `end` is the last base of the window, and LCR ends are exclusive
                     output.push(LCR {
                         name: name.clone(),
                         start: s,
-                        end,
+                        end: end + 1,
+                        score: s_total,
                     });
                 }
 
@@ -130,6 +126,37 @@ pub fn fasterdust(
     }
 }
 
+/// ln(n) for n in 0..len, with ln(0) stored as 0.0
+fn ln_table(len: usize) -> Vec<f64> {
+    (0..len)
+        .map(|n| if n == 0 { 0.0 } else { (n as f64).ln() })
+        .collect()
+}
+
+/// Check "good" for a whole sequence, used to explain single calls.
+/// Sequences with a non-ACGT k-mer are never good.
+pub fn is_good_sequence(seq: &str, k: usize, t: f64) -> bool {
+    let seq = seq.as_bytes();
+    if k == 0 || seq.len() < k { return false; }
+
+    let kmers = precompute_kmers(seq, k);
+    let ln_table = ln_table(seq.len() - k + 3);
+
+    let mut counts: FxHashMap<u64, u32> = FxHashMap::default();
+    let mut s_total = 0.0f64;
+    for &kmer in &kmers[..=seq.len() - k] {
+        let code = match kmer {
+            Some(c) => c,
+            None => return false,
+        };
+        let entry = counts.entry(code).or_insert(0);
+        *entry += 1;
+        s_total += ln_table[*entry as usize] - t;
+    }
+
+    is_good_window(&kmers, 0, seq.len() - 1, k, t, &ln_table, s_total)
+}
+
 /// Check "good": no proper prefix or proper suffix has higher score than S(window).
 /// We recompute prefix/suffix scores **exactly** over the k-mers of this window.
 /// Early-out as soon as we detect a violation.
@@ -154,8 +181,8 @@ fn is_good_window(
         let mut counts: FxHashMap<u64, u32> = FxHashMap::default();
         let mut s = 0.0f64;
 
-        for pos in start_k..last_k { // excludes the last k-mer => proper prefix
-            let code = match kmers[pos] {
+        for &kmer in &kmers[start_k..last_k] { // excludes the last k-mer => proper prefix
+            let code = match kmer {
                 Some(c) => c,
                 None => return false, // shouldn't happen if outer loop screened, but be safe
             };
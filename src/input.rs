@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use flate2::read::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+};
+
+use crate::{
+    fasta_parsing::{Fastq, FastqIterator, BUFF_SIZE},
+    sam::{BamIterator, SamIterator, BAM_MAGIC},
+    twobit::is_twobit,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Fasta,
+    Fastq,
+    TwoBit,
+    Sam,
+    Bam,
+}
+
+/// Bytes read from the start of a file to detect its format
+const DETECT_LEN: u64 = 1 << 16;
+/// Record types that can start a SAM header
+const SAM_HEADER_TAGS: [&[u8]; 5] = [b"@HD\t", b"@SQ\t", b"@RG\t", b"@PG\t", b"@CO\t"];
+
+/// Readers that can also seek, boxed so plain and BGZF files share one type
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+pub fn is_gzip(path: &str) -> Result<bool> {
+    let mut magic = [0u8; 2];
+    let read = File::open(path)?.read(&mut magic)?;
+    Ok(read == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Opens a file for streaming, transparently decompressing gzip and BGZF input
+pub fn open_input(path: &str) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path)?;
+    if is_gzip(path)? {
+        let buffered = BufReader::with_capacity(BUFF_SIZE, file);
+        Ok(Box::new(MultiGzDecoder::new(buffered)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Detects the format from the file contents, looking past gzip compression
+pub fn detect_format(path: &str) -> Result<InputFormat> {
+    if is_twobit(path)? {
+        return Ok(InputFormat::TwoBit);
+    }
+    let mut head = Vec::new();
+    open_input(path)?.take(DETECT_LEN).read_to_end(&mut head)?;
+    if head.starts_with(&BAM_MAGIC) {
+        return Ok(InputFormat::Bam);
+    }
+
+    let text = head.trim_ascii_start();
+    let first_line = text.split(|&b| b == b'\n').next().unwrap_or_default();
+    match text.first() {
+        // Empty files are read as empty fasta files
+        None | Some(b'>') => Ok(InputFormat::Fasta),
+        Some(b'@') if SAM_HEADER_TAGS.iter().any(|tag| first_line.starts_with(tag)) => {
+            Ok(InputFormat::Sam)
+        }
+        Some(b'@') => Ok(InputFormat::Fastq),
+        // SAM files without a header start straight with an 11-column record
+        _ if first_line.iter().filter(|&&b| b == b'\t').count() >= 10 => Ok(InputFormat::Sam),
+        _ => Err(anyhow!(
+            "Unknown input format for {path}: expected fasta, fastq, 2bit, SAM or BAM"
+        )),
+    }
+}
+
+/// Rough number of bases in an uncompressed input, from its size, for progress estimates
+pub fn estimate_bases(path: &str) -> Result<Option<u64>> {
+    if is_gzip(path)? {
+        return Ok(None);
+    }
+    let size = File::open(path)?.metadata()?.len();
+    Ok(match detect_format(path)? {
+        InputFormat::Fasta => Some(size),
+        // Qualities take as much space as the bases
+        InputFormat::Fastq => Some(size / 2),
+        InputFormat::TwoBit => Some(size * 4),
+        InputFormat::Sam | InputFormat::Bam => None,
+    })
+}
+
+/// Opens a file of reads in any of the read formats
+pub fn open_reads(path: &str) -> Result<Box<dyn Iterator<Item = Result<Fastq>> + Send>> {
+    let reader = open_input(path)?;
+    match detect_format(path)? {
+        InputFormat::Fastq => Ok(Box::new(FastqIterator::new(reader))),
+        InputFormat::Sam => Ok(Box::new(SamIterator::new(reader))),
+        InputFormat::Bam => Ok(Box::new(BamIterator::new(BufReader::with_capacity(
+            BUFF_SIZE, reader,
+        )))),
+        _ => Err(anyhow!("{path} is not a fastq, SAM or BAM file of reads")),
+    }
+}
@@ -0,0 +1,194 @@
+use log::{info, Level, LevelFilter, Log, Metadata, Record};
+use rustc_hash::FxHashMap;
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Writes log lines to stderr, so they never mix with output written to stdout
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        // One write per line, so lines from worker threads don't interleave
+        let line = format!("[lcr {level}] {}\n", record.args());
+        let _ = std::io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the stderr logger. Quiet runs only log errors; otherwise each `-v`
+/// lowers the level by one from info.
+pub fn init_logging(quiet: bool, verbose: u8) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    // Only fails if a logger is already installed
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+/// How often progress is logged
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Records shown by name in a progress line
+const MAX_ACTIVE_SHOWN: usize = 3;
+
+/// Counts finished records and bases, and logs a progress line every `interval` from
+/// a timer thread, so a long record being scanned does not leave the log silent
+pub struct Progress {
+    start: Instant,
+    /// What is counted, such as records or reads
+    unit: &'static str,
+    /// Estimated bases in the input, for the time remaining
+    total_bases: Option<u64>,
+    records: AtomicU64,
+    bases: AtomicU64,
+    /// Records being scanned, by record number: name, length and start time
+    active: Mutex<FxHashMap<usize, (String, usize, Instant)>>,
+    /// Dropping or taking the sender stops the timer thread
+    stop: Mutex<Option<Sender<()>>>,
+    timer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Progress {
+    /// Starts counting, and the timer thread that logs progress until `finish`
+    pub fn start(unit: &'static str, total_bases: Option<u64>, interval: Duration) -> Arc<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let progress = Arc::new(Self {
+            start: Instant::now(),
+            unit,
+            total_bases,
+            records: AtomicU64::new(0),
+            bases: AtomicU64::new(0),
+            active: Mutex::new(FxHashMap::default()),
+            stop: Mutex::new(Some(stop)),
+            timer: Mutex::new(None),
+        });
+        // Weak, so a Progress that is dropped without `finish` still ends the thread
+        let weak = Arc::downgrade(&progress);
+        let timer = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                match weak.upgrade() {
+                    Some(progress) => progress.report(),
+                    None => break,
+                }
+            }
+        });
+        *lock(&progress.timer) = Some(timer);
+        progress
+    }
+
+    /// Marks record `id` as being scanned, so progress lines can name it
+    pub fn record_started(&self, id: usize, name: &str, len: usize) {
+        lock(&self.active).insert(id, (name.to_owned(), len, Instant::now()));
+    }
+
+    pub fn record_done(&self, id: usize, bases: usize) {
+        lock(&self.active).remove(&id);
+        self.add(1, bases);
+    }
+
+    /// Counts finished records that were not marked as started, such as a batch of reads
+    pub fn add(&self, records: usize, bases: usize) {
+        self.records.fetch_add(records as u64, Ordering::Relaxed);
+        self.bases.fetch_add(bases as u64, Ordering::Relaxed);
+    }
+
+    /// Stops the timer thread and logs the final counts
+    pub fn finish(&self) {
+        drop(lock(&self.stop).take());
+        if let Some(timer) = lock(&self.timer).take() {
+            let _ = timer.join();
+        }
+        let elapsed = self.start.elapsed();
+        info!(
+            "Finished {} {}, {} bases in {elapsed:.2?}",
+            self.records.load(Ordering::Relaxed),
+            self.unit,
+            self.bases.load(Ordering::Relaxed)
+        );
+    }
+
+    fn report(&self) {
+        let records = self.records.load(Ordering::Relaxed);
+        let bases = self.bases.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = bases as f64 / elapsed.max(1e-9);
+        let remaining = match self.total_bases {
+            Some(total) if total > bases && rate > 0.0 => {
+                let secs = (total - bases) as f64 / rate;
+                format!(", about {} remaining", format_duration(secs))
+            }
+            _ => String::new(),
+        };
+
+        let active = lock(&self.active);
+        let mut scanning: Vec<_> = active.values().collect();
+        // Longest running first
+        scanning.sort_by_key(|(_, _, started)| *started);
+        let mut names: Vec<String> = scanning
+            .iter()
+            .take(MAX_ACTIVE_SHOWN)
+            .map(|(name, len, started)| {
+                let secs = started.elapsed().as_secs_f64();
+                format!("{name} ({len} bp, {})", format_duration(secs))
+            })
+            .collect();
+        if scanning.len() > MAX_ACTIVE_SHOWN {
+            names.push(format!("{} more", scanning.len() - MAX_ACTIVE_SHOWN));
+        }
+        drop(active);
+        let scanning = if names.is_empty() {
+            String::new()
+        } else {
+            format!("; scanning {}", names.join(", "))
+        };
+
+        info!(
+            "Processed {records} {}, {bases} bases ({:.1} kb/s){remaining}{scanning}",
+            self.unit,
+            rate / 1000.0
+        );
+    }
+}
+
+/// Locks a progress field, even if a thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
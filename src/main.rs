@@ -1,137 +1,281 @@
 pub mod fasta_parsing;
+pub mod byte_parsing;
 pub mod slowdust;
 pub mod slowdust2;
 pub mod command_line;
+pub mod classify;
+pub mod explain;
+pub mod region;
+pub mod faidx;
+pub mod bgzf;
+pub mod input;
+pub mod twobit;
+pub mod fasterdust;
+pub mod score_track;
+pub mod mmap_input;
+pub mod output;
+pub mod sam;
+pub mod scan;
+pub mod summary;
+pub mod logging;
+pub mod reads;
 
-use anyhow::{Ok, Result};
-use clap::Parser;
+use anyhow::{anyhow, Ok, Result};
+use log::debug;
+use clap::{Parser, ValueEnum};
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, BufWriter, Write},
+    borrow::Cow,
+    io::Write,
     sync::{Arc, Mutex},
     time::Instant,
 };
+use rustc_hash::FxHashSet;
 use threadpool::ThreadPool;
 
 use crate::{
-    command_line::DustArgs, fasta_parsing::{FastaIterator, BUFF_SIZE}, slowdust::{longdust_score, merge_intervals, slowdust}, slowdust2::{is_good_seq, slowdust2}
+    classify::classify_intervals, command_line::{Command, DustArgs}, explain::explain, faidx::faidx, input::{detect_format, estimate_bases, open_input, open_reads, InputFormat}, logging::{init_logging, Progress, PROGRESS_INTERVAL}, reads::{filter_reads, mask_reads, trim_reads}, fasta_parsing::{FastaIterator, SeqRecord}, mmap_input::MappedFasta, output::{open_output, write_header, OrderedWriter, Coordinates, write_lcr, write_record_header, write_record_summary, OutputFormat, OutputOptions}, region::{read_regions, read_regions_bed, Region}, score_track::{max_covering_score, record_score_ending, write_track, write_track_header, TrackMode}, scan::{scan, select_intervals}, summary::{write_summary, RecordSummary, RunParams}, twobit::TwoBitReader
 };
 
-fn main() -> Result<()> {
-    
-    //print_score("ctcctctcctttcttctctccatccCCCCTCCATCCCcgtctcctttctcctctccatccccctctccatccccctctccatctccctctcctttctcctctccatccccctctcctttctccctctccatccccctctCCTTTCTTC",7, 0.6);
-    //return Ok(());
+/// A record to scan, the position of its first base and the length of the full record
+type ScanItem = (Box<dyn SeqRecord + Send>, usize, usize);
+
+fn boxed<S: SeqRecord + Send + 'static>(record: Result<(S, usize, usize)>) -> Result<ScanItem> {
+    record.map(|(seq, offset, record_len)| {
+        (Box::new(seq) as Box<dyn SeqRecord + Send>, offset, record_len)
+    })
+}
 
+/// First word of the record header, used in the output
+fn record_name(record: &dyn SeqRecord) -> &str {
+    record.get_name().split_whitespace().next().unwrap_or_default()
+}
+
+/// A whole record starts at 0 and is as long as its sequence
+fn whole<S: SeqRecord>(record: S) -> (S, usize, usize) {
+    let record_len = record.sequence_len();
+    (record, 0, record_len)
+}
+
+fn main() -> Result<()> {
     let args = DustArgs::parse();
+    let run_now = Instant::now();
+    init_logging(args.quiet, args.verbose);
+
+    match &args.command {
+        Some(Command::Explain(explain_args)) => return explain(explain_args),
+        Some(Command::Faidx(faidx_args)) => return faidx(faidx_args),
+        Some(Command::MaskReads(mask_args)) => return mask_reads(mask_args),
+        Some(Command::FilterReads(filter_args)) => return filter_reads(filter_args),
+        Some(Command::TrimReads(trim_args)) => return trim_reads(trim_args),
+        None => {}
+    }
     
+    // Both are required by clap unless a subcommand is given
+    let (Some(input_file), Some(output_file)) = (args.input_file, args.output_file) else {
+        return Err(anyhow!("--input and --output are required"));
+    };
+
     let num_threads: usize = args.threads;
 
     let pool = ThreadPool::new(num_threads);
 
-    let file = File::open(args.input_file)?;
-    let reader = BufReader::with_capacity(BUFF_SIZE, file);
+    // BED and GFF3 fix their own convention, so a different one cannot be honoured
+    let fixed_coordinates = match args.output_format {
+        OutputFormat::Bed => Some(Coordinates::ZeroBased),
+        OutputFormat::Gff3 => Some(Coordinates::OneBased),
+        OutputFormat::Tsv | OutputFormat::Jsonl => None,
+    };
+    let coordinates = match (args.coordinates, fixed_coordinates) {
+        (Some(asked), Some(fixed)) if asked != fixed => {
+            return Err(anyhow!(
+                "--coordinates {} cannot be used with --output-format {}",
+                asked.to_possible_value().unwrap().get_name(),
+                args.output_format.to_possible_value().unwrap().get_name()
+            ));
+        }
+        (asked, fixed) => asked.or(fixed).unwrap_or(Coordinates::ZeroBased),
+    };
+    let output_options = OutputOptions {
+        format: args.output_format,
+        coordinates,
+        with_score: args.merge.no_merge,
+        with_sequence: args.with_sequence,
+        max_sequence_len: args.max_sequence_len,
+    };
+    let record_summary = args.record_summary;
+    if record_summary && output_options.format != OutputFormat::Jsonl {
+        return Err(anyhow!("--record-summary needs --output-format jsonl"));
+    }
+    // Records are written in input order, whichever worker finishes first
+    let mut writer = OrderedWriter::new(open_output(&output_file, args.compression)?);
+    write_header(writer.get_mut(), &output_options)?;
+    writer.get_mut().flush()?;
+    let writer = Arc::new(Mutex::new(writer));
 
-    let output = File::create(args.output_file)?;
-    let writer = Arc::new(Mutex::new(BufWriter::with_capacity(BUFF_SIZE, output)));
+    let track_writer = match &args.score_track {
+        Some(path) => {
+            let mut track_writer = open_output(path, args.compression)?;
+            write_track_header(&mut track_writer, args.track_format)?;
+            Some(Arc::new(Mutex::new(OrderedWriter::new(track_writer))))
+        }
+        None => None,
+    };
+    let (track_format, track_mode) = (args.track_format, args.track_mode);
 
-    {
-        let mut header_guard = writer.lock().unwrap();
-        let _ = writeln!(header_guard, "Name\tStart\tEnd\n");
-        header_guard.flush()?;
+    let mut regions = args
+        .regions
+        .iter()
+        .map(|region| Region::parse(region))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(path) = &args.regions_bed {
+        regions.extend(read_regions_bed(path)?);
     }
+    let regions_given = !regions.is_empty();
+    let region_names: Vec<String> = regions.iter().map(Region::to_string).collect();
+
+    // Each record comes with the position of its first base, so slices keep full-record coordinates
+    let iterator: Box<dyn Iterator<Item = Result<ScanItem>>> = if args.mmap {
+        if !regions.is_empty() {
+            return Err(anyhow!("--mmap cannot be combined with --region or --regions-bed"));
+        }
+        if detect_format(&input_file)? != InputFormat::Fasta {
+            return Err(anyhow!("--mmap only supports fasta input"));
+        }
+        let records = MappedFasta::open(&input_file)?.records();
+        Box::new(records.map(|record| boxed(record.map(whole))))
+    } else if !regions.is_empty() {
+        Box::new(read_regions(&input_file, regions)?.map(boxed))
+    } else {
+        match detect_format(&input_file)? {
+            InputFormat::TwoBit => {
+                let records = TwoBitReader::open(&input_file)?.records();
+                Box::new(records.map(|record| boxed(record.map(|twobit| whole(twobit.to_fasta())))))
+            }
+            InputFormat::Fastq | InputFormat::Sam | InputFormat::Bam => {
+                let records = open_reads(&input_file)?;
+                Box::new(records.map(|record| boxed(record.map(|read| whole(read.to_fasta())))))
+            }
+            InputFormat::Fasta => {
+                let records = FastaIterator::new(open_input(&input_file)?);
+                Box::new(records.map(|record| boxed(record.map(whole))))
+            }
+        }
+    };
+    let classes = Arc::new(args.classes.clone());
+    let params = args.scan;
+    let merge_args = args.merge;
+    let summaries = Arc::new(Mutex::new(Vec::new()));
 
-    let iterator = FastaIterator::new(reader);
+    let total_bases = if regions_given { None } else { estimate_bases(&input_file)? };
+    let progress = Progress::start("records", total_bases, PROGRESS_INTERVAL);
+    // First error hit by a worker, returned once the pool has finished
+    let worker_error = Arc::new(Mutex::new(None));
+    // Records that already have a header
+    let mut declared = FxHashSet::default();
 
-    for line in iterator {
-        let fasta = line?;
+    for (record_num, line) in iterator.enumerate() {
+        let (fasta, offset, record_len) = line?;
+        let summaries = Arc::clone(&summaries);
+        let progress = Arc::clone(&progress);
         let writer_clone = Arc::clone(&writer);
+        let classes = Arc::clone(&classes);
+        let track_writer = track_writer.clone();
+        let worker_error = Arc::clone(&worker_error);
+        // Several regions of one record share a single header, before the first of them
+        let first_of_record = declared.insert(record_name(&*fasta).to_owned());
 
         pool.execute(move || {
-            //let seq = fasta.get_sequence();
-            let loop_now = Instant::now();
-            let mut output = Vec::new();
-            let name = fasta
-                .get_name()
-                .split_whitespace()
-                .next()
-                .unwrap_or_default();
-
-            slowdust2(&fasta, 7, 5000, 0.6, &mut output);
-            let merged = merge_intervals(output);
-            let loop_elapsed = loop_now.elapsed();
-            println!("1 Loop finished in {loop_elapsed:.2?} for {name}");
-            let mut guard = writer_clone.lock().unwrap_or_else(|e| e.into_inner());
-            for lcr in merged {
-                let _ = writeln!(guard, "{}", lcr);
+            let result = (|| -> Result<()> {
+                let loop_now = Instant::now();
+                let name = record_name(&*fasta);
+                progress.record_started(record_num, name, fasta.sequence_len());
+
+                let output = scan(&params, &*fasta);
+                if let Some(track_writer) = track_writer {
+                    let track = match track_mode {
+                        TrackMode::End => record_score_ending(
+                            &*fasta,
+                            params.kmer.k,
+                            params.window,
+                            params.kmer.threshold,
+                        ),
+                        TrackMode::Cover => max_covering_score(fasta.sequence_len(), &output),
+                    };
+                    let mut lines = Vec::new();
+                    write_track(&mut lines, track_format, name, offset, &track)?;
+                    track_writer.lock().unwrap_or_else(|e| e.into_inner()).write(record_num, lines)?;
+                }
+                let seq_len = fasta.sequence_len();
+                let selected = select_intervals(output, &merge_args);
+                let classifications = classify_intervals(&selected, &*fasta, merge_args.no_merge);
+                let merged: Vec<_> = selected
+                    .into_iter()
+                    .zip(classifications)
+                    .map(|(mut lcr, classification)| {
+                        // Only read when printed, as a wrapped mapped record has to copy them
+                        let bases = if output_options.with_sequence {
+                            fasta.bases(lcr.start.min(seq_len), lcr.end.min(seq_len))
+                        } else {
+                            Cow::Borrowed("")
+                        };
+                        lcr.start += offset;
+                        lcr.end += offset;
+                        (lcr, classification, bases)
+                    })
+                    .filter(|(_, classification, _)| {
+                        classes.is_empty() || classes.contains(&classification.get_class())
+                    })
+                    .collect();
+                let loop_elapsed = loop_now.elapsed();
+                debug!("Scanned {name} ({seq_len} bp) in {loop_elapsed:.2?}");
+                let lcrs: Vec<_> = merged.iter().map(|(lcr, _, _)| lcr.clone()).collect();
+                let summary = RecordSummary::new(name, &*fasta, &lcrs, loop_elapsed);
+                summaries.lock().unwrap_or_else(|e| e.into_inner()).push((record_num, summary));
+                let mut lines = Vec::new();
+                if first_of_record {
+                    write_record_header(&mut lines, &output_options, name, record_len)?;
+                }
+                for (lcr, classification, bases) in &merged {
+                    write_lcr(&mut lines, &output_options, lcr, classification, &params, bases)?;
+                }
+                if record_summary {
+                    write_record_summary(&mut lines, &output_options, name, seq_len, &lcrs)?;
+                }
+                writer_clone.lock().unwrap_or_else(|e| e.into_inner()).write(record_num, lines)?;
+                progress.record_done(record_num, seq_len);
+                Ok(())
+            })();
+            if let Err(err) = result {
+                worker_error.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert(err);
             }
-            guard.flush().expect("Failed to flush writer");
         });
     }
     pool.join();
-    println!("Finished running");
-
-    Ok(())
-}
-
-
-//For manual sequence checking
-fn print_score(seq: &str, k: usize, t: f64) {
-    let score = longdust_score(seq, k, t);
-
-    let mut kmer_counts = HashMap::new();
-    let mut score_2 = 0.0;
-    for i in k..=seq.len() {
-        let window = &seq[..i];
-
-        let new_mer = &window[window.len() - k..];
-        let entry = kmer_counts.entry(new_mer).or_insert(0.0);
-        *entry += 1.0;
-
-        let c_new: f64 = *entry;
-        let window_score = score_2 + (c_new).ln() - t;
-
-        score_2 = window_score;
-    }
-
-    if score < t{
-        println!("slowdust-below threshold: {score}")
-    } else {
-        println!("slowdust: {score}")
-    }
-
-    if score_2 < t {
-        println!("slowdust2-below threshold: {score_2}")
-    } else {
-        println!("slowdust2: {score_2}")
+    if let Some(err) = worker_error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        return Err(err);
     }
-
-    let mut is_good = true;
-    for j in 0..=seq.len() {
-        let prefix = &seq[..j];
-        let suffix = &seq[j..];
-        if longdust_score(prefix, k, t) > score || longdust_score(suffix, k, t) > score {
-            is_good = false;
-            break;
-        }
+    if pool.panic_count() > 0 {
+        return Err(anyhow!("{} records failed to scan", pool.panic_count()));
     }
-
-    if is_good{
-        println!("slowdust: Good")
-    } else {
-        println!("slowdust: Not good")
+    progress.finish();
+    writer.lock().unwrap_or_else(|e| e.into_inner()).finish()?;
+    if let Some(track_writer) = &track_writer {
+        track_writer.lock().unwrap_or_else(|e| e.into_inner()).finish()?;
     }
 
-    if is_good_seq(seq, score_2, k, t) {
-        println!("slowdust2: Good")
-    } else {
-        println!("slowdust2: Not good")
+    if let Some(path) = &args.summary {
+        let mut summaries = std::mem::take(&mut *summaries.lock().unwrap());
+        summaries.sort_by_key(|(record_num, _)| *record_num);
+        let records: Vec<_> = summaries.into_iter().map(|(_, summary)| summary).collect();
+        let run_params = RunParams {
+            scan: &params,
+            merge: &merge_args,
+            classes: &args.classes,
+            regions: &region_names,
+        };
+        write_summary(path, args.compression, &run_params, &records, run_now.elapsed())?;
     }
 
-
-    if is_good_seq(seq, score, k, t) {
-        println!("slowdust2 with slowdust score: Good seq")
-    } else {
-        println!("slowdust2 with slowdust score: Not good seq")
-    }
+    Ok(())
 }
+
@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use memmap2::Mmap;
+use std::{
+    borrow::Cow,
+    fs::File,
+    ops::Range,
+    sync::{Arc, OnceLock},
+    vec,
+};
+
+use crate::{
+    faidx::{FaiEntry, FaiIndex},
+    fasta_parsing::SeqRecord,
+    input::is_gzip,
+};
+
+/// An uncompressed fasta file mapped into memory. Records are located through
+/// the `.fai` index next to the file, or a one-pass scan of its lines if there is none.
+pub struct MappedFasta {
+    map: Arc<Mmap>,
+    index: FaiIndex,
+}
+
+impl MappedFasta {
+    pub fn open(path: &str) -> Result<Self> {
+        if is_gzip(path)? {
+            return Err(anyhow!("{path} is compressed and cannot be memory-mapped"));
+        }
+        let file = File::open(path)?;
+        // SAFETY: the map is only read. As with any mmap, the file must not be
+        // truncated or rewritten by another process while it is being scanned.
+        let map = unsafe { Mmap::map(&file)? };
+        let index = match FaiIndex::find(path)? {
+            Some(index) => index,
+            None => FaiIndex::build(&map[..])
+                .map_err(|err| anyhow!("Cannot memory-map {path}: {err}"))?,
+        };
+        Ok(Self {
+            map: Arc::new(map),
+            index,
+        })
+    }
+
+    /// Iterates over every record with sequence, in index order
+    pub fn records(self) -> MappedIterator {
+        MappedIterator {
+            map: self.map,
+            entries: self.index.entries().to_vec().into_iter(),
+        }
+    }
+}
+
+pub struct MappedIterator {
+    map: Arc<Mmap>,
+    entries: vec::IntoIter<FaiEntry>,
+}
+
+impl Iterator for MappedIterator {
+    type Item = Result<MappedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Records without sequence are skipped, as when streaming
+        let entry = self.entries.find(|entry| entry.length > 0)?;
+        Some(MappedRecord::new(Arc::clone(&self.map), entry))
+    }
+}
+
+/// A record read straight from the mapped file. Line-wrapped sequences are never joined
+/// as a whole for scanning: `bases` copies only the range asked for.
+pub struct MappedRecord {
+    map: Arc<Mmap>,
+    entry: FaiEntry,
+    /// Byte range of the sequence lines, newlines included
+    range: Range<usize>,
+    /// The whole sequence, built only if something asks for it as one string
+    joined: OnceLock<String>,
+}
+
+impl MappedRecord {
+    fn new(map: Arc<Mmap>, entry: FaiEntry) -> Result<Self> {
+        // base_offset divides by line_bases, which a record with bases cannot have at 0
+        if entry.line_bases == 0 {
+            return Err(anyhow!("Invalid fai entry for {}: zero bases per line", entry.name));
+        }
+        let start = entry.offset as usize;
+        let end = entry.base_offset(entry.length - 1) as usize + 1;
+        if end > map.len() {
+            return Err(anyhow!("fai entry for {} is past the end of the file", entry.name));
+        }
+        // Lines are checked one by one, so that any run of them is valid once joined
+        for line in map[start..end].chunks(entry.line_width) {
+            std::str::from_utf8(&line[..line.len().min(entry.line_bases)])
+                .map_err(|err| anyhow!("Invalid sequence encoding in {}: {err}", entry.name))?;
+        }
+        Ok(Self {
+            map,
+            entry,
+            range: start..end,
+            joined: OnceLock::new(),
+        })
+    }
+
+    /// The sequence bytes of `start..end`, one slice per line
+    fn lines(&self, start: usize, end: usize) -> impl Iterator<Item = &[u8]> {
+        let line_bases = self.entry.line_bases;
+        (start / line_bases * line_bases..end).step_by(line_bases).map(move |line_start| {
+            let from = self.entry.base_offset(start.max(line_start)) as usize;
+            let to = self.entry.base_offset(end.min(line_start + line_bases) - 1) as usize + 1;
+            &self.map[from..to]
+        })
+    }
+}
+
+impl SeqRecord for MappedRecord {
+    fn get_name(&self) -> &str {
+        &self.entry.name
+    }
+
+    fn get_sequence(&self) -> &str {
+        if self.is_contiguous() {
+            // SAFETY: the range was checked to be UTF-8 when the record was created
+            return unsafe { std::str::from_utf8_unchecked(&self.map[self.range.clone()]) };
+        }
+        self.joined.get_or_init(|| self.bases(0, self.entry.length).into_owned())
+    }
+
+    fn sequence_len(&self) -> usize {
+        self.entry.length
+    }
+
+    fn bases(&self, start: usize, end: usize) -> Cow<'_, str> {
+        assert!(start <= end && end <= self.entry.length, "bases {start}..{end} out of range");
+        if start == end {
+            return Cow::Borrowed("");
+        }
+        if self.is_contiguous() {
+            return Cow::Borrowed(&self.get_sequence()[start..end]);
+        }
+        let mut bases = Vec::with_capacity(end - start);
+        for line in self.lines(start, end) {
+            bases.extend_from_slice(line);
+        }
+        // Whole lines are valid UTF-8, so only a range that splits a character can fail,
+        // as slicing a `str` there would
+        Cow::Owned(String::from_utf8(bases).expect("bases split a multi-byte character"))
+    }
+
+    fn is_contiguous(&self) -> bool {
+        self.entry.length <= self.entry.line_bases
+    }
+}
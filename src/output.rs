@@ -0,0 +1,353 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use flate2::{write::GzEncoder, Compression};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    bgzf::BgzfWriter, classify::Classification, command_line::ScanArgs, fasta_parsing::BUFF_SIZE,
+    slowdust::{covered_bases, LCR},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputCompression {
+    /// BGZF for paths ending in .gz or .bgz, uncompressed otherwise
+    Auto,
+    None,
+    Gzip,
+    /// Block gzip, which tabix and samtools can index
+    Bgzf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated with a header line
+    Tsv,
+    /// BED6 with the raw score in column 7, without a header so it can be sorted and indexed with tabix
+    Bed,
+    /// GFF3 low_complexity_region features, 1-based inclusive
+    Gff3,
+    /// One JSON object per LCR, with its composition and motif
+    Jsonl,
+}
+
+/// An output stream that has to be finished to be complete, such as a compressed one
+pub trait OutputWriter: Write + Send {
+    /// Writes any pending data and trailer, reporting errors that dropping the writer would hide
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl OutputWriter for BufWriter<File> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write + Send> OutputWriter for GzEncoder<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write + Send> OutputWriter for BgzfWriter<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        BgzfWriter::finish(self)
+    }
+}
+
+/// Creates an output file, compressed as `compression` asks. Call `finish` once
+/// everything is written; dropping the writer also finishes it, but ignores errors.
+pub fn open_output(path: &str, compression: OutputCompression) -> Result<Box<dyn OutputWriter>> {
+    let compression = match compression {
+        OutputCompression::Auto if path.ends_with(".gz") || path.ends_with(".bgz") => {
+            OutputCompression::Bgzf
+        }
+        OutputCompression::Auto => OutputCompression::None,
+        other => other,
+    };
+    let file = BufWriter::with_capacity(BUFF_SIZE, File::create(path)?);
+    Ok(match compression {
+        OutputCompression::Gzip => Box::new(GzEncoder::new(file, Compression::default())),
+        OutputCompression::Bgzf => Box::new(BgzfWriter::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+/// Writes the output of records that finish in any order in the order they were read,
+/// so that sorted input gives sorted output. Records are numbered from 0 and every
+/// number has to be written, if only with an empty buffer.
+pub struct OrderedWriter<W: Write> {
+    writer: W,
+    next: usize,
+    /// Records finished before every record ahead of them
+    pending: BTreeMap<usize, Vec<u8>>,
+}
+
+impl<W: Write> OrderedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// The underlying writer, for the header and anything else before the first record
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Writes `output` of record `record_num` once all records before it are written
+    pub fn write(&mut self, record_num: usize, output: Vec<u8>) -> io::Result<()> {
+        self.pending.insert(record_num, output);
+        while let Some(output) = self.pending.remove(&self.next) {
+            self.writer.write_all(&output)?;
+            self.next += 1;
+        }
+        self.writer.flush()
+    }
+}
+
+impl OrderedWriter<Box<dyn OutputWriter>> {
+    pub fn finish(&mut self) -> io::Result<()> {
+        debug_assert!(self.pending.is_empty(), "records {:?} are missing their predecessors", self.pending.keys());
+        self.writer.finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Coordinates {
+    /// 0-based start, exclusive end
+    ZeroBased,
+    /// 1-based start, inclusive end
+    OneBased,
+}
+
+/// What goes into the LCR output besides the intervals
+#[derive(Debug, Clone, Copy)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Convention of TSV and JSONL coordinates. BED is always 0-based and GFF3 1-based.
+    pub coordinates: Coordinates,
+    /// Add a TSV score column, so the overlapping intervals of `--no-merge` can be told apart.
+    /// The other formats always have the score.
+    pub with_score: bool,
+    /// Append the bases of each LCR
+    pub with_sequence: bool,
+    /// Longer sequences are cut to this many bases and marked as truncated
+    pub max_sequence_len: Option<usize>,
+}
+
+impl OutputOptions {
+    /// Start of an LCR in the chosen convention. Ends are the same in both.
+    fn start(&self, lcr: &LCR) -> usize {
+        match self.coordinates {
+            Coordinates::ZeroBased => lcr.get_start(),
+            Coordinates::OneBased => lcr.get_start() + 1,
+        }
+    }
+
+    /// The bases to print for an LCR, and whether they were truncated
+    fn sequence<'a>(&self, bases: &'a str) -> (&'a str, bool) {
+        match self.max_sequence_len {
+            Some(max_len) if bases.len() > max_len => (&bases[..max_len], true),
+            _ => (bases, false),
+        }
+    }
+}
+
+/// BED scores are integers from 0 to 1000, so LCR scores are multiplied by this and capped
+const BED_SCORE_SCALE: f64 = 10.0;
+
+fn bed_score(score: f64) -> u32 {
+    (score * BED_SCORE_SCALE).round().clamp(0.0, 1000.0) as u32
+}
+
+/// Marks truncated sequences in the text formats
+const TRUNCATION_MARK: &str = "...";
+
+pub fn write_header<W: Write>(writer: &mut W, options: &OutputOptions) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Tsv => {
+            write!(writer, "Name\tStart\tEnd\tClass")?;
+            if options.with_score {
+                write!(writer, "\tScore")?;
+            }
+            if options.with_sequence {
+                write!(writer, "\tSequence")?;
+            }
+            writeln!(writer, "\n")
+        }
+        OutputFormat::Bed => Ok(()),
+        OutputFormat::Gff3 => writeln!(writer, "##gff-version 3"),
+        OutputFormat::Jsonl => Ok(()),
+    }
+}
+
+/// Written once per record, before its first LCRs. `length` is the length of
+/// the whole record, even when only regions of it are scanned.
+pub fn write_record_header<W: Write>(
+    writer: &mut W,
+    options: &OutputOptions,
+    name: &str,
+    length: usize,
+) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Gff3 => writeln!(writer, "##sequence-region {name} 1 {length}"),
+        _ => Ok(()),
+    }
+}
+
+/// `bases` are the bases of the LCR
+pub fn write_lcr<W: Write>(
+    writer: &mut W,
+    options: &OutputOptions,
+    lcr: &LCR,
+    classification: &Classification,
+    params: &ScanArgs,
+    bases: &str,
+) -> io::Result<()> {
+    let class = classification.get_class();
+    let (sequence, truncated) = options.sequence(bases);
+    match options.format {
+        OutputFormat::Tsv | OutputFormat::Bed => {
+            match options.format {
+                OutputFormat::Tsv => {
+                    write!(
+                        writer,
+                        "{}\t{}\t{}\t{class}",
+                        lcr.get_name(),
+                        options.start(lcr),
+                        lcr.get_end()
+                    )?;
+                    if options.with_score {
+                        write!(writer, "\t{:.4}", lcr.get_score())?;
+                    }
+                }
+                // The BED name column holds the class and the score column the scaled score.
+                // LCRs have no strand, so the strand column is `.`, followed by the raw score
+                // and then the sequence (BED6+1 or BED6+2).
+                _ => write!(
+                    writer,
+                    "{lcr}\t{class}\t{}\t.\t{:.4}",
+                    bed_score(lcr.get_score()),
+                    lcr.get_score()
+                )?,
+            }
+            if options.with_sequence {
+                let mark = if truncated { TRUNCATION_MARK } else { "" };
+                write!(writer, "\t{sequence}{mark}")?;
+            }
+            writeln!(writer)
+        }
+        OutputFormat::Gff3 => {
+            write!(
+                writer,
+                "{}\tlcr\tlow_complexity_region\t{}\t{}\t{:.4}\t.\t.\t",
+                lcr.get_name(),
+                lcr.get_start() + 1,
+                lcr.get_end(),
+                lcr.get_score()
+            )?;
+            write!(
+                writer,
+                "class={class};score={:.4};algorithm={};k={};window={};threshold={}",
+                lcr.get_score(),
+                params.algorithm,
+                params.kmer.k,
+                params.window,
+                params.kmer.threshold
+            )?;
+            // Cryptic LCRs have no repeat unit
+            if classification.get_period() > 0 {
+                write!(
+                    writer,
+                    ";motif={};period={}",
+                    classification.get_motif(),
+                    classification.get_period()
+                )?;
+            }
+            writeln!(writer)
+        }
+        OutputFormat::Jsonl => {
+            let [a, c, g, t, n] = composition(bases);
+            let acgt = a + c + g + t;
+            let mut object = json!({
+                "type": "lcr",
+                "name": lcr.get_name(),
+                "start": options.start(lcr),
+                "end": lcr.get_end(),
+                "length": lcr.get_end() - lcr.get_start(),
+                "score": lcr.get_score(),
+                "class": class.to_string(),
+                "period": classification.get_period(),
+                "purity": classification.get_purity(),
+                "motif": classification.get_motif(),
+                "composition": {
+                    "A": a,
+                    "C": c,
+                    "G": g,
+                    "T": t,
+                    "N": n,
+                    "gc": if acgt > 0 { (c + g) as f64 / acgt as f64 } else { 0.0 },
+                },
+                "algorithm": params.algorithm.to_string(),
+                "k": params.kmer.k,
+                "window": params.window,
+                "threshold": params.kmer.threshold,
+            });
+            if options.with_sequence {
+                object["sequence"] = json!(sequence);
+                object["sequence_truncated"] = json!(truncated);
+            }
+            serde_json::to_writer(&mut *writer, &object)?;
+            writeln!(writer)
+        }
+    }
+}
+
+/// Written after the LCRs of a record, for jsonl output only. `lcrs` are the
+/// merged LCRs written for it and `length` is the number of bases scanned.
+pub fn write_record_summary<W: Write>(
+    writer: &mut W,
+    options: &OutputOptions,
+    name: &str,
+    length: usize,
+    lcrs: &[LCR],
+) -> io::Result<()> {
+    if options.format != OutputFormat::Jsonl {
+        return Ok(());
+    }
+    let masked = covered_bases(lcrs);
+    let object = json!({
+        "type": "record",
+        "name": name,
+        "length": length,
+        "lcrs": lcrs.len(),
+        "masked_bp": masked,
+        "masked_fraction": if length > 0 { masked as f64 / length as f64 } else { 0.0 },
+    });
+    serde_json::to_writer(&mut *writer, &object)?;
+    writeln!(writer)
+}
+
+/// Counts of A, C, G, T and anything else, ignoring case
+fn composition(bases: &str) -> [usize; 5] {
+    let mut counts = [0; 5];
+    for base in bases.bytes() {
+        let i = match base.to_ascii_uppercase() {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => 4,
+        };
+        counts[i] += 1;
+    }
+    counts
+}
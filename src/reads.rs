@@ -0,0 +1,530 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::info;
+use std::{
+    io::{self, Write},
+    sync::Arc,
+    thread,
+};
+
+use crate::{
+    command_line::{FilterReadsArgs, MaskReadsArgs, PairArgs, ScanArgs, TrimReadsArgs},
+    fasta_parsing::Fastq,
+    input::{estimate_bases, open_reads},
+    logging::{Progress, PROGRESS_INTERVAL},
+    output::{open_output, OutputCompression, OutputWriter},
+    scan::scan,
+    slowdust::{merge_intervals, LCR},
+};
+
+/// Reads scored together, so each batch can be split over the threads and written in order
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MaskMode {
+    /// Lowercase masked bases
+    Lower,
+    /// Replace masked bases with N
+    N,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReadMetric {
+    /// Fraction of the read covered by LCRs
+    Fraction,
+    /// Best score of any LCR in the read, 0 if it has none
+    Score,
+}
+
+impl ReadMetric {
+    /// Value of the metric for a read of `len` bases with merged LCRs `lcrs`
+    pub fn value(&self, len: usize, lcrs: &[LCR]) -> f64 {
+        match self {
+            ReadMetric::Fraction if len == 0 => 0.0,
+            ReadMetric::Fraction => covered(lcrs) as f64 / len as f64,
+            ReadMetric::Score => lcrs.iter().map(LCR::get_score).fold(0.0, f64::max),
+        }
+    }
+}
+
+/// Bases covered by merged, so non-overlapping, LCRs
+fn covered(lcrs: &[LCR]) -> usize {
+    lcrs.iter().map(|lcr| lcr.get_end() - lcr.get_start()).sum()
+}
+
+/// Merged LCRs of one read
+pub fn read_lcrs(read: &Fastq, params: &ScanArgs) -> Vec<LCR> {
+    merge_intervals(scan(params, read))
+}
+
+/// Maps `f` over `items` on up to `threads` threads, keeping the order of `items`
+pub fn par_map<T: Sync, U: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    if threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk_size = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Worker thread panicked"))
+            .collect()
+    })
+}
+
+/// One read, or both mates of a pair, which are always kept or dropped together
+pub type ReadUnit = Vec<Fastq>;
+
+/// Reads `--input` as single reads, or as pairs with `--input2` or `--interleaved`
+pub fn open_units(
+    input_file: &str,
+    pairs: &PairArgs,
+) -> Result<Box<dyn Iterator<Item = Result<ReadUnit>> + Send>> {
+    let first = open_reads(input_file)?;
+    Ok(match (&pairs.input_file2, pairs.interleaved) {
+        (Some(input_file2), _) => Box::new(MatePairs {
+            first,
+            second: Some(open_reads(input_file2)?),
+            pair_num: 0,
+        }),
+        (None, true) => Box::new(MatePairs {
+            first,
+            second: None,
+            pair_num: 0,
+        }),
+        (None, false) => Box::new(first.map(|read| read.map(|read| vec![read]))),
+    })
+}
+
+/// Pairs mates from two files, or from consecutive reads of one file when `second` is None
+struct MatePairs {
+    first: Box<dyn Iterator<Item = Result<Fastq>> + Send>,
+    second: Option<Box<dyn Iterator<Item = Result<Fastq>> + Send>>,
+    pair_num: usize,
+}
+
+impl MatePairs {
+    fn next_pair(&mut self) -> Result<Option<ReadUnit>> {
+        let mate1 = self.first.next().transpose()?;
+        let mate2 = match self.second.as_mut() {
+            Some(second) => second.next().transpose()?,
+            None => self.first.next().transpose()?,
+        };
+        self.pair_num += 1;
+        match (mate1, mate2) {
+            (None, None) => Ok(None),
+            (Some(mate1), Some(mate2)) => {
+                if mate_name(mate1.get_id()) != mate_name(mate2.get_id()) {
+                    return Err(anyhow!(
+                        "Mates of pair {} have different names: {} and {}",
+                        self.pair_num,
+                        mate1.get_id(),
+                        mate2.get_id()
+                    ));
+                }
+                Ok(Some(vec![mate1, mate2]))
+            }
+            _ => Err(anyhow!("Pair {} is missing a mate", self.pair_num)),
+        }
+    }
+}
+
+impl Iterator for MatePairs {
+    type Item = Result<ReadUnit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// Read name without its comment or a /1 or /2 mate suffix
+fn mate_name(id: &str) -> &str {
+    let name = id.split_whitespace().next().unwrap_or_default();
+    name.strip_suffix("/1")
+        .or_else(|| name.strip_suffix("/2"))
+        .unwrap_or(name)
+}
+
+/// Starts progress logging for a read mode, sized by both input files
+fn start_progress(input_file: &str, pairs: &PairArgs) -> Result<Arc<Progress>> {
+    let mut total_bases = estimate_bases(input_file)?;
+    if let Some(input_file2) = &pairs.input_file2 {
+        total_bases = total_bases.zip(estimate_bases(input_file2)?).map(|(a, b)| a + b);
+    }
+    Ok(Progress::start("reads", total_bases, PROGRESS_INTERVAL))
+}
+
+/// Counts the reads and bases of a finished batch
+fn batch_done(progress: &Progress, batch: &[ReadUnit]) {
+    let reads = batch.iter().map(Vec::len).sum();
+    let bases = batch.iter().flatten().map(|read| read.get_sequence().len()).sum();
+    progress.add(reads, bases);
+}
+
+/// Reads up to `BATCH_SIZE` units. An empty batch means the input is done.
+pub fn next_batch<I: Iterator<Item = Result<ReadUnit>>>(units: &mut I) -> Result<Vec<ReadUnit>> {
+    units.by_ref().take(BATCH_SIZE).collect()
+}
+
+/// Fastq outputs for the first and second mates. Without a second output,
+/// both mates go to the first one, interleaved.
+pub struct MateWriters {
+    first: Box<dyn OutputWriter>,
+    second: Option<Box<dyn OutputWriter>>,
+}
+
+impl MateWriters {
+    pub fn open(path: &str, path2: Option<&str>, compression: OutputCompression) -> Result<Self> {
+        Ok(Self {
+            first: open_output(path, compression)?,
+            second: path2.map(|path| open_output(path, compression)).transpose()?,
+        })
+    }
+
+    pub fn write(&mut self, unit: &[Fastq]) -> io::Result<()> {
+        for (mate, read) in unit.iter().enumerate() {
+            match self.second.as_mut() {
+                Some(second) if mate == 1 => write_fastq(second, read)?,
+                _ => write_fastq(&mut self.first, read)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.first.finish()?;
+        if let Some(second) = self.second.as_mut() {
+            second.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn write_fastq<W: Write + ?Sized>(writer: &mut W, read: &Fastq) -> io::Result<()> {
+    writeln!(
+        writer,
+        "@{}\n{}\n+\n{}",
+        read.get_id(),
+        read.get_sequence(),
+        read.get_quality()
+    )
+}
+
+/// Masks the bases of `read` inside `lcrs`, and sets their quality to `masked_quality` if given
+pub fn mask_read(read: &Fastq, lcrs: &[LCR], mode: MaskMode, masked_quality: Option<u8>) -> Fastq {
+    let mut sequence = read.get_sequence().as_bytes().to_vec();
+    let mut quality = read.get_quality().as_bytes().to_vec();
+    for lcr in lcrs {
+        let range = lcr.get_start()..lcr.get_end().min(sequence.len());
+        for base in &mut sequence[range.clone()] {
+            *base = match mode {
+                MaskMode::Lower => base.to_ascii_lowercase(),
+                MaskMode::N => b'N',
+            };
+        }
+        if let Some(q) = masked_quality {
+            quality[range].fill(q + 33);
+        }
+    }
+    // Only ASCII bytes were replaced, so both stay valid UTF-8
+    Fastq::new(
+        read.get_id().to_owned(),
+        String::from_utf8(sequence).expect("masked sequence is ASCII"),
+        String::from_utf8(quality).expect("masked quality is ASCII"),
+    )
+}
+
+pub fn mask_reads(args: &MaskReadsArgs) -> Result<()> {
+    let mut units = open_units(&args.input_file, &args.pairs)?;
+    let progress = start_progress(&args.input_file, &args.pairs)?;
+    let mut writers =
+        MateWriters::open(&args.output_file, args.output_file2.as_deref(), args.compression)?;
+    let (mut total_reads, mut masked_bases) = (0, 0);
+
+    loop {
+        let batch = next_batch(&mut units)?;
+        if batch.is_empty() {
+            break;
+        }
+        let masked = par_map(&batch, args.threads, |unit| {
+            unit.iter()
+                .map(|read| {
+                    let lcrs = read_lcrs(read, &args.scan);
+                    (mask_read(read, &lcrs, args.mask, args.masked_quality), covered(&lcrs))
+                })
+                .unzip::<_, _, Vec<_>, Vec<_>>()
+        });
+        for (unit, covered) in masked {
+            writers.write(&unit)?;
+            total_reads += unit.len();
+            masked_bases += covered.iter().sum::<usize>();
+        }
+        batch_done(&progress, &batch);
+    }
+    writers.finish()?;
+    progress.finish();
+    info!("Masked {masked_bases} bases in {total_reads} reads");
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PairRule {
+    /// A pair fails if either mate fails
+    Either,
+    /// A pair fails only if both mates fail
+    Both,
+    /// A pair fails if the metric over both mates together fails
+    Combined,
+}
+
+/// True if the read or pair is below the cutoff
+fn unit_passes(unit: &[Fastq], args: &FilterReadsArgs) -> bool {
+    let mates: Vec<(usize, Vec<LCR>)> = unit
+        .iter()
+        .map(|read| (read.get_sequence().len(), read_lcrs(read, &args.scan)))
+        .collect();
+    let passes = |len: usize, lcrs: &[LCR]| args.metric.value(len, lcrs) < args.cutoff;
+
+    match args.pair_rule {
+        PairRule::Either => mates.iter().all(|(len, lcrs)| passes(*len, lcrs)),
+        PairRule::Both => mates.iter().any(|(len, lcrs)| passes(*len, lcrs)),
+        PairRule::Combined => {
+            let len = mates.iter().map(|(len, _)| len).sum();
+            let lcrs: Vec<LCR> = mates.into_iter().flat_map(|(_, lcrs)| lcrs).collect();
+            passes(len, &lcrs)
+        }
+    }
+}
+
+pub fn filter_reads(args: &FilterReadsArgs) -> Result<()> {
+    let mut units = open_units(&args.input_file, &args.pairs)?;
+    let progress = start_progress(&args.input_file, &args.pairs)?;
+    let mut pass_writers =
+        MateWriters::open(&args.pass_file, args.pass_file2.as_deref(), args.compression)?;
+    let mut fail_writers = match &args.fail_file {
+        Some(path) => Some(MateWriters::open(path, args.fail_file2.as_deref(), args.compression)?),
+        None => None,
+    };
+    let (mut passed, mut failed) = (0, 0);
+
+    loop {
+        let batch = next_batch(&mut units)?;
+        if batch.is_empty() {
+            break;
+        }
+        let passes = par_map(&batch, args.threads, |unit| unit_passes(unit, args));
+        for (unit, pass) in batch.iter().zip(passes) {
+            if pass {
+                passed += 1;
+                pass_writers.write(unit)?;
+            } else {
+                failed += 1;
+                if let Some(writers) = fail_writers.as_mut() {
+                    writers.write(unit)?;
+                }
+            }
+        }
+        batch_done(&progress, &batch);
+    }
+    pass_writers.finish()?;
+    if let Some(writers) = fail_writers.as_mut() {
+        writers.finish()?;
+    }
+    progress.finish();
+    let unit_name = if args.pairs.is_paired() { "pairs" } else { "reads" };
+    info!("{passed} {unit_name} passed and {failed} failed");
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TrimEnds {
+    /// Both ends of the read
+    Both,
+    /// Only the 5' end, the start of the read
+    Five,
+    /// Only the 3' end, the end of the read
+    Three,
+}
+
+/// Bases trimmed from each end of a read
+#[derive(Debug, Clone, Copy)]
+pub struct Trimmed {
+    pub five: usize,
+    pub three: usize,
+}
+
+/// Cuts the LCRs of at least `min_trim` bases that touch the chosen ends of `read`.
+/// A read that is one LCR is trimmed to nothing, counted at the 5' end.
+pub fn trim_read(read: &Fastq, lcrs: &[LCR], ends: TrimEnds, min_trim: usize) -> (Fastq, Trimmed) {
+    let len = read.get_sequence().len();
+    let long = |lcr: &&LCR| lcr.get_end() - lcr.get_start() >= min_trim;
+
+    let start = match ends {
+        TrimEnds::Three => 0,
+        _ => lcrs
+            .iter()
+            .filter(long)
+            .find(|lcr| lcr.get_start() == 0)
+            .map_or(0, |lcr| lcr.get_end().min(len)),
+    };
+    let end = match ends {
+        TrimEnds::Five => len,
+        _ => lcrs
+            .iter()
+            .filter(long)
+            .find(|lcr| lcr.get_end() >= len)
+            .map_or(len, |lcr| lcr.get_start())
+            .max(start),
+    };
+    let trimmed = Fastq::new(
+        read.get_id().to_owned(),
+        read.get_sequence()[start..end].to_owned(),
+        read.get_quality()[start..end].to_owned(),
+    );
+    (
+        trimmed,
+        Trimmed {
+            five: start,
+            three: len - end,
+        },
+    )
+}
+
+pub fn trim_reads(args: &TrimReadsArgs) -> Result<()> {
+    let mut units = open_units(&args.input_file, &args.pairs)?;
+    let progress = start_progress(&args.input_file, &args.pairs)?;
+    let mut writers =
+        MateWriters::open(&args.output_file, args.output_file2.as_deref(), args.compression)?;
+    let mut report = match &args.report_file {
+        Some(path) => {
+            let mut report = open_output(path, args.compression)?;
+            writeln!(report, "Name\tLength\tTrimmed5\tTrimmed3\tTrimmedLength")?;
+            Some(report)
+        }
+        None => None,
+    };
+    let (mut total_reads, mut trimmed_reads, mut trimmed_bases) = (0, 0, 0);
+
+    loop {
+        let batch = next_batch(&mut units)?;
+        if batch.is_empty() {
+            break;
+        }
+        let trimmed = par_map(&batch, args.threads, |unit| {
+            unit.iter()
+                .map(|read| trim_read(read, &read_lcrs(read, &args.scan), args.ends, args.min_trim))
+                .unzip::<_, _, Vec<_>, Vec<_>>()
+        });
+        for (unit, (trimmed_unit, lengths)) in batch.iter().zip(trimmed) {
+            writers.write(&trimmed_unit)?;
+            for (read, trimmed) in unit.iter().zip(lengths) {
+                let length = read.get_sequence().len();
+                let cut = trimmed.five + trimmed.three;
+                total_reads += 1;
+                trimmed_reads += usize::from(cut > 0);
+                trimmed_bases += cut;
+                if let Some(report) = report.as_mut() {
+                    writeln!(
+                        report,
+                        "{}\t{length}\t{}\t{}\t{}",
+                        read.get_id().split_whitespace().next().unwrap_or_default(),
+                        trimmed.five,
+                        trimmed.three,
+                        length - cut
+                    )?;
+                }
+            }
+        }
+        batch_done(&progress, &batch);
+    }
+    writers.finish()?;
+    if let Some(report) = report.as_mut() {
+        report.finish()?;
+    }
+    progress.finish();
+    info!("Trimmed {trimmed_bases} bases from {trimmed_reads} of {total_reads} reads");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(id: &str, sequence: &str) -> Fastq {
+        Fastq::new(id.to_owned(), sequence.to_owned(), "I".repeat(sequence.len()))
+    }
+
+    fn lcr(start: usize, end: usize) -> LCR {
+        LCR::new("r".to_owned(), start, end, 1.0)
+    }
+
+    fn trimmed(read: &Fastq, lcrs: &[LCR], ends: TrimEnds, min_trim: usize) -> (String, usize, usize) {
+        let (read, trimmed) = trim_read(read, lcrs, ends, min_trim);
+        (read.get_sequence().to_owned(), trimmed.five, trimmed.three)
+    }
+
+    fn mates(reads: Vec<Fastq>) -> Box<dyn Iterator<Item = Result<Fastq>> + Send> {
+        Box::new(reads.into_iter().map(Ok))
+    }
+
+    #[test]
+    fn trim_read_cuts_chosen_ends() {
+        let polya = read("r", "AAAAAACGTACGTTTTTT");
+        let lcrs = [lcr(0, 6), lcr(12, 18)];
+        assert_eq!(trimmed(&polya, &lcrs, TrimEnds::Both, 5), ("CGTACG".to_owned(), 6, 6));
+        assert_eq!(trimmed(&polya, &lcrs, TrimEnds::Five, 5), ("CGTACGTTTTTT".to_owned(), 6, 0));
+        assert_eq!(trimmed(&polya, &lcrs, TrimEnds::Three, 5), ("AAAAAACGTACG".to_owned(), 0, 6));
+    }
+
+    #[test]
+    fn trim_read_keeps_lcrs_shorter_than_min_trim() {
+        let read = read("r", "AAAAAACGTACGTTTTTT");
+        let lcrs = [lcr(0, 6), lcr(12, 18)];
+        assert_eq!(trimmed(&read, &lcrs, TrimEnds::Both, 7), ("AAAAAACGTACGTTTTTT".to_owned(), 0, 0));
+        // LCRs inside the read are never cut
+        assert_eq!(trimmed(&read, &[lcr(3, 15)], TrimEnds::Both, 1), ("AAAAAACGTACGTTTTTT".to_owned(), 0, 0));
+    }
+
+    #[test]
+    fn trim_read_empties_a_read_that_is_one_lcr() {
+        let read = read("r", "AAAAAAAAAA");
+        let lcrs = [lcr(0, 10)];
+        assert_eq!(trimmed(&read, &lcrs, TrimEnds::Both, 5), (String::new(), 10, 0));
+        assert_eq!(trimmed(&read, &lcrs, TrimEnds::Five, 5), (String::new(), 10, 0));
+        assert_eq!(trimmed(&read, &lcrs, TrimEnds::Three, 5), (String::new(), 0, 10));
+    }
+
+    #[test]
+    fn mate_pairs_match_names_across_files() {
+        let first = vec![read("p1/1 comment", "ACGT"), read("p2/1", "ACGT")];
+        let second = vec![read("p1/2", "TTTT"), read("p2/2 other", "TTTT")];
+        let mut pairs = MatePairs { first: mates(first), second: Some(mates(second)), pair_num: 0 };
+        let pair = pairs.next().unwrap().unwrap();
+        assert_eq!((pair[0].get_id(), pair[1].get_id()), ("p1/1 comment", "p1/2"));
+        assert!(pairs.next().unwrap().is_ok());
+        assert!(pairs.next().is_none());
+    }
+
+    #[test]
+    fn mate_pairs_reject_different_names() {
+        let first = vec![read("p1/1", "ACGT"), read("p2/1", "ACGT")];
+        let second = vec![read("p1/2", "TTTT"), read("p3/2", "TTTT")];
+        let mut pairs = MatePairs { first: mates(first), second: Some(mates(second)), pair_num: 0 };
+        assert!(pairs.next().unwrap().is_ok());
+        let err = pairs.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Mates of pair 2 have different names: p2/1 and p3/2");
+    }
+
+    #[test]
+    fn mate_pairs_reject_odd_interleaved_count() {
+        let reads = vec![read("p1/1", "ACGT"), read("p1/2", "TTTT"), read("p2/1", "ACGT")];
+        let mut pairs = MatePairs { first: mates(reads), second: None, pair_num: 0 };
+        assert_eq!(pairs.next().unwrap().unwrap().len(), 2);
+        let err = pairs.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Pair 2 is missing a mate");
+    }
+}
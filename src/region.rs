@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Result};
+use core::fmt;
+use rustc_hash::FxHashMap;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    mem,
+};
+
+use crate::{
+    faidx::IndexedFasta,
+    input::open_input,
+    twobit::{is_twobit, TwoBitReader},
+    fasta_parsing::{Fasta, FastaIterator},
+};
+
+/// A slice of a named sequence, stored 0-based half-open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl Region {
+    pub fn new(name: String, start: usize, end: Option<usize>) -> Self {
+        Self { name, start, end }
+    }
+
+    /// Parses a samtools-style region: `name`, `name:start` or `name:start-end`,
+    /// with 1-based inclusive coordinates. Commas in numbers are ignored.
+    pub fn parse(region: &str) -> Result<Self> {
+        let (name, range) = match region.rsplit_once(':') {
+            Some((name, range)) if !range.is_empty() => (name, Some(range)),
+            _ => (region, None),
+        };
+        if name.is_empty() {
+            return Err(anyhow!("Invalid region {region}: missing sequence name"));
+        }
+        let Some(range) = range else {
+            return Ok(Self::new(name.to_owned(), 0, None));
+        };
+
+        let parse_pos = |pos: &str| -> Result<usize> {
+            pos.replace(',', "")
+                .parse::<usize>()
+                .map_err(|err| anyhow!("Invalid region {region}: {err}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, "")) => (parse_pos(start)?, None),
+            Some((start, end)) => (parse_pos(start)?, Some(parse_pos(end)?)),
+            None => (parse_pos(range)?, None),
+        };
+        if start == 0 {
+            return Err(anyhow!("Invalid region {region}: coordinates are 1-based"));
+        }
+        if end.is_some_and(|end| end < start) {
+            return Err(anyhow!("Invalid region {region}: end is before start"));
+        }
+        Ok(Self::new(name.to_owned(), start - 1, end))
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_start(&self) -> usize {
+        self.start
+    }
+
+    /// The end of the region, clamped to a sequence of length `len`
+    pub fn end_within(&self, len: usize) -> usize {
+        self.end.map_or(len, |end| end.min(len))
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (_, Some(end)) => write!(f, "{}:{}-{}", self.name, self.start + 1, end),
+            // A whole record is printed as its bare name, as samtools does
+            (0, None) => write!(f, "{}", self.name),
+            (start, None) => write!(f, "{}:{}", self.name, start + 1),
+        }
+    }
+}
+
+/// Reads regions from a BED file (0-based half-open). Header, track and browser lines are skipped.
+pub fn read_regions_bed(path: &str) -> Result<Vec<Region>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut regions = Vec::new();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            return Err(anyhow!("Invalid bed format at {path}:{}", line_num + 1));
+        }
+        let parse = |field: &str| -> Result<usize> {
+            field
+                .trim()
+                .parse::<usize>()
+                .map_err(|err| anyhow!("Invalid bed format at {path}:{}: {err}", line_num + 1))
+        };
+        let (start, end) = (parse(fields[1])?, parse(fields[2])?);
+        if end < start {
+            return Err(anyhow!(
+                "Invalid bed format at {path}:{}: end is before start",
+                line_num + 1
+            ));
+        }
+        regions.push(Region::new(fields[0].to_owned(), start, Some(end)));
+    }
+    Ok(regions)
+}
+
+/// A slice of a record, the 0-based position of its first base in the full record,
+/// and the length of the full record
+pub type RegionSeq = (Fasta, usize, usize);
+
+/// Reads the sequence of each region from the fasta at `path`, using its `.fai`
+/// index for random access when there is one and streaming the file otherwise.
+/// `.2bit` files are always randomly accessed.
+pub fn read_regions(
+    path: &str,
+    regions: Vec<Region>,
+) -> Result<Box<dyn Iterator<Item = Result<RegionSeq>> + Send>> {
+    if is_twobit(path)? {
+        let mut twobit = TwoBitReader::open(path)?;
+        return Ok(Box::new(regions.into_iter().map(move |region| {
+            let seq = twobit.fetch_region(&region)?;
+            let record_len = twobit.length(region.get_name())?;
+            Ok((Fasta::new(region.name, seq), region.start, record_len))
+        })));
+    }
+
+    match IndexedFasta::open(path)? {
+        Some(mut fasta) => Ok(Box::new(regions.into_iter().map(move |region| {
+            let seq = fasta.fetch_region(&region)?;
+            // fetch_region has checked that the record is in the index
+            let record_len =
+                fasta.get_index().get(region.get_name()).map_or(0, |entry| entry.length);
+            Ok((Fasta::new(region.name, seq), region.start, record_len))
+        }))),
+        None => {
+            Ok(Box::new(StreamedRegions::new(FastaIterator::new(open_input(path)?), regions)))
+        }
+    }
+}
+
+/// Returns the sequence of a single region
+pub fn fetch_region(path: &str, region: &Region) -> Result<String> {
+    match read_regions(path, vec![region.clone()])?.next() {
+        Some(record) => Ok(record?.0.sequence),
+        None => Err(anyhow!("Sequence {} not found in {path}", region.get_name())),
+    }
+}
+
+/// Slices regions out of records as they are streamed, for files without an index
+pub struct StreamedRegions<T: Read> {
+    records: FastaIterator<T>,
+    /// Regions not yet matched to a record, by sequence name
+    pending: FxHashMap<String, Vec<Region>>,
+    ready: Vec<RegionSeq>,
+}
+
+impl<T: Read> StreamedRegions<T> {
+    pub fn new(records: FastaIterator<T>, regions: Vec<Region>) -> Self {
+        let mut pending: FxHashMap<String, Vec<Region>> = FxHashMap::default();
+        for region in regions {
+            pending.entry(region.name.clone()).or_default().push(region);
+        }
+        Self {
+            records,
+            pending,
+            ready: Vec::new(),
+        }
+    }
+}
+
+impl<T: Read> Iterator for StreamedRegions<T> {
+    type Item = Result<RegionSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() {
+            if self.pending.is_empty() {
+                return None;
+            }
+            let fasta = match self.records.next() {
+                Some(Ok(fasta)) => fasta,
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    let mut missing: Vec<String> =
+                        mem::take(&mut self.pending).into_keys().collect();
+                    missing.sort();
+                    return Some(Err(anyhow!("Sequences not found: {}", missing.join(", "))));
+                }
+            };
+
+            let name = fasta.get_name().split_whitespace().next().unwrap_or_default();
+            let Some(regions) = self.pending.remove(name) else {
+                continue;
+            };
+            let seq = fasta.get_sequence();
+            // Reversed so regions come out in the order they were given
+            for region in regions.into_iter().rev() {
+                let end = region.end_within(seq.len());
+                if region.get_start() >= end {
+                    return Some(Err(anyhow!(
+                        "Region {region} is outside of {name} ({} bp)",
+                        seq.len()
+                    )));
+                }
+                let slice = seq[region.get_start()..end].to_owned();
+                let slice = Fasta::new(fasta.get_name().to_owned(), slice);
+                self.ready.push((slice, region.start, seq.len()));
+            }
+        }
+        self.ready.pop().map(Ok)
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::{anyhow, Result};
+use std::io::{self, ErrorKind, Read};
+
+use crate::{byte_parsing::LineReader, fasta_parsing::Fastq};
+
+pub const BAM_MAGIC: [u8; 4] = *b"BAM\x01";
+
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_SECONDARY: u16 = 0x100;
+const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+/// 4-bit BAM base codes
+const BAM_BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+/// Quality given to reads stored without qualities, as `samtools fastq` does
+const MISSING_QUALITY: u8 = b'"';
+
+/// Secondary and supplementary records repeat a read that has a primary record
+fn is_primary(flag: u16) -> bool {
+    flag & (FLAG_SECONDARY | FLAG_SUPPLEMENTARY) == 0
+}
+
+/// Builds the read as sequenced, undoing the reverse complement of reverse-strand alignments
+fn to_fastq(name: &[u8], flag: u16, mut sequence: Vec<u8>, mut quality: Vec<u8>) -> Result<Fastq> {
+    if flag & FLAG_REVERSE != 0 {
+        sequence.reverse();
+        for base in &mut sequence {
+            *base = complement(*base);
+        }
+        quality.reverse();
+    }
+    let to_string = |bytes: Vec<u8>| {
+        String::from_utf8(bytes).map_err(|err| anyhow!("Invalid text encoding: {err}"))
+    };
+    Ok(Fastq::new(
+        to_string(name.to_vec())?,
+        to_string(sequence)?,
+        to_string(quality)?,
+    ))
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        other => other,
+    }
+}
+
+/// Reads the primary records of a plain-text SAM file as reads
+pub struct SamIterator<R: Read> {
+    lines: LineReader<R>,
+    /// Set after an error, as in `FastqIterator`
+    failed: bool,
+}
+
+impl<R: Read> SamIterator<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: LineReader::new(reader),
+            failed: false,
+        }
+    }
+
+    fn read_next(&mut self) -> Result<Option<Fastq>> {
+        loop {
+            let line_num = self.lines.line_num() + 1;
+            let Some(line) = self
+                .lines
+                .next_line()
+                .map_err(|err| anyhow!("Invalid line/file format: {err}"))?
+            else {
+                return Ok(None);
+            };
+            if line.is_empty() || line[0] == b'@' {
+                continue;
+            }
+
+            let fields: Vec<&[u8]> = line.splitn(12, |&b| b == b'\t').collect();
+            if fields.len() < 11 {
+                return Err(anyhow!(
+                    "Invalid SAM format at line {line_num}: expected 11 fields, found {}",
+                    fields.len()
+                ));
+            }
+            let flag = std::str::from_utf8(fields[1])
+                .ok()
+                .and_then(|flag| flag.parse::<u16>().ok())
+                .ok_or_else(|| anyhow!("Invalid SAM format at line {line_num}: bad FLAG"))?;
+            if !is_primary(flag) {
+                continue;
+            }
+
+            let sequence = match fields[9] {
+                b"*" => Vec::new(),
+                seq => seq.to_vec(),
+            };
+            let quality = match fields[10] {
+                b"*" => vec![MISSING_QUALITY; sequence.len()],
+                qual if qual.len() == sequence.len() => qual.to_vec(),
+                _ => {
+                    return Err(anyhow!(
+                        "Invalid SAM format at line {line_num}: QUAL length does not match SEQ"
+                    ))
+                }
+            };
+            return to_fastq(fields[0], flag, sequence, quality).map(Some);
+        }
+    }
+}
+
+impl<R: Read> Iterator for SamIterator<R> {
+    type Item = Result<Fastq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = self.read_next().transpose();
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+/// Reads the primary records of a BAM file as reads. `reader` must yield the
+/// decompressed stream, as `open_input` does for BGZF files.
+pub struct BamIterator<R: Read> {
+    reader: R,
+    header_read: bool,
+    record_num: usize,
+    failed: bool,
+}
+
+impl<R: Read> BamIterator<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            header_read: false,
+            record_num: 0,
+            failed: false,
+        }
+    }
+
+    /// Skips the header text and reference dictionary
+    fn read_header(&mut self) -> Result<()> {
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        if magic != BAM_MAGIC {
+            return Err(anyhow!("Invalid BAM format: bad magic"));
+        }
+        let l_text = self.read_u32()? as u64;
+        io::copy(&mut (&mut self.reader).take(l_text), &mut io::sink())?;
+        let n_ref = self.read_u32()?;
+        for _ in 0..n_ref {
+            let l_name = self.read_u32()? as u64;
+            // Name, then the reference length
+            io::copy(&mut (&mut self.reader).take(l_name + 4), &mut io::sink())?;
+        }
+        Ok(())
+    }
+
+    fn read_next(&mut self) -> Result<Option<Fastq>> {
+        if !self.header_read {
+            self.read_header()?;
+            self.header_read = true;
+        }
+        loop {
+            let mut size = [0u8; 4];
+            match self.reader.read_exact(&mut size) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+            self.record_num += 1;
+            // The size is only trusted as far as the stream goes, so a corrupt one
+            // cannot allocate more than the file holds
+            let size = u32::from_le_bytes(size) as usize;
+            let mut record = Vec::new();
+            (&mut self.reader).take(size as u64).read_to_end(&mut record).map_err(|err| {
+                anyhow!("Invalid BAM format in record {}: {err}", self.record_num)
+            })?;
+            if record.len() < size {
+                return Err(anyhow!(
+                    "Invalid BAM format in record {}: truncated, {} of {size} bytes",
+                    self.record_num,
+                    record.len()
+                ));
+            }
+            if let Some(read) = self.parse_record(&record)? {
+                return Ok(Some(read));
+            }
+        }
+    }
+
+    /// Decodes one record after its block size, or None if it is not a primary record
+    fn parse_record(&self, record: &[u8]) -> Result<Option<Fastq>> {
+        let invalid = |msg: &str| anyhow!("Invalid BAM format in record {}: {msg}", self.record_num);
+        // Fixed fields are 32 bytes, from refID to tlen
+        if record.len() < 32 {
+            return Err(invalid("record too short"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([record[i], record[i + 1]]);
+        let l_read_name = record[8] as usize;
+        let n_cigar_op = u16_at(12) as usize;
+        let flag = u16_at(14);
+        let l_seq = u32::from_le_bytes(record[16..20].try_into().unwrap()) as usize;
+        if !is_primary(flag) {
+            return Ok(None);
+        }
+
+        let name_start = 32;
+        let seq_start = name_start + l_read_name + 4 * n_cigar_op;
+        let qual_start = seq_start + l_seq.div_ceil(2);
+        if qual_start + l_seq > record.len() || l_read_name == 0 {
+            return Err(invalid("fields past the end of the record"));
+        }
+        // The read name is NUL terminated
+        let name = &record[name_start..name_start + l_read_name - 1];
+
+        let sequence = (0..l_seq)
+            .map(|i| {
+                let byte = record[seq_start + i / 2];
+                let code = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                BAM_BASES[code as usize]
+            })
+            .collect();
+        let qual = &record[qual_start..qual_start + l_seq];
+        // A first quality of 0xff means the read has no qualities
+        let quality = if qual.first() == Some(&0xff) {
+            vec![MISSING_QUALITY; l_seq]
+        } else {
+            qual.iter().map(|q| q.saturating_add(33).min(b'~')).collect()
+        };
+        to_fastq(name, flag, sequence, quality).map(Some)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+impl<R: Read> Iterator for BamIterator<R> {
+    type Item = Result<Fastq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = self.read_next().transpose();
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One unmapped BAM record, without its block size
+    fn bam_record(name: &str, flag: u16, seq: &str, qual: Option<&[u8]>) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend((-1i32).to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.push(name.len() as u8 + 1);
+        record.push(0);
+        record.extend(4680u16.to_le_bytes());
+        record.extend(0u16.to_le_bytes());
+        record.extend(flag.to_le_bytes());
+        record.extend((seq.len() as u32).to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.extend((-1i32).to_le_bytes());
+        record.extend(0i32.to_le_bytes());
+        record.extend(name.as_bytes());
+        record.push(0);
+        let codes: Vec<u8> = seq
+            .bytes()
+            .map(|b| BAM_BASES.iter().position(|&base| base == b).unwrap() as u8)
+            .collect();
+        record.extend(codes.chunks(2).map(|pair| pair[0] << 4 | pair.get(1).unwrap_or(&0)));
+        match qual {
+            Some(qual) => record.extend(qual),
+            None => record.extend(vec![0xff; seq.len()]),
+        }
+        record
+    }
+
+    /// The decompressed stream of a BAM file with one reference and `records`
+    fn bam(records: &[Vec<u8>]) -> Vec<u8> {
+        let text = b"@HD\tVN:1.6\n";
+        let mut bam = BAM_MAGIC.to_vec();
+        bam.extend((text.len() as u32).to_le_bytes());
+        bam.extend(text);
+        bam.extend(1u32.to_le_bytes());
+        bam.extend(5u32.to_le_bytes());
+        bam.extend(b"chr1\0");
+        bam.extend(1000u32.to_le_bytes());
+        for record in records {
+            bam.extend((record.len() as u32).to_le_bytes());
+            bam.extend(record);
+        }
+        bam
+    }
+
+    fn reads<I: Iterator<Item = Result<Fastq>>>(iter: I) -> Vec<(String, String, String)> {
+        iter.map(|read| {
+            let read = read.unwrap();
+            owned(read.get_id(), read.get_sequence(), read.get_quality())
+        })
+        .collect()
+    }
+
+    fn owned(name: &str, seq: &str, qual: &str) -> (String, String, String) {
+        (name.to_owned(), seq.to_owned(), qual.to_owned())
+    }
+
+    #[test]
+    fn decodes_bam_records() {
+        let stream = bam(&[
+            bam_record("r1", 0, "ACGTN", Some(&[0, 10, 20, 30, 40])),
+            bam_record("r1", FLAG_SECONDARY, "ACGTN", None),
+            bam_record("r2", FLAG_REVERSE, "AACG", Some(&[1, 2, 3, 4])),
+            bam_record("r3", 0, "GG", None),
+        ]);
+        assert_eq!(
+            reads(BamIterator::new(&stream[..])),
+            [
+                owned("r1", "ACGTN", "!+5?I"),
+                owned("r2", "CGTT", "%$#\""),
+                owned("r3", "GG", "\"\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_bam_records() {
+        let mut record = bam_record("r1", 0, "ACGT", Some(&[30; 4]));
+        record.truncate(record.len() - 2);
+        let stream = bam(&[record]);
+        let results: Vec<_> = BamIterator::new(&stream[..]).collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        assert!(BamIterator::new(&b"BAM\x02"[..]).next().unwrap().is_err());
+
+        // A corrupt block size larger than the stream is an error, not an allocation
+        let mut stream = bam(&[]);
+        stream.extend(u32::MAX.to_le_bytes());
+        stream.extend(bam_record("r1", 0, "ACGT", None));
+        let err = BamIterator::new(&stream[..]).next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn reads_sam_records() {
+        let sam = "@HD\tVN:1.6\n\
+            r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n\
+            r1\t2048\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n\
+            r2\t16\tchr1\t1\t60\t3M\t*\t0\t0\tAAC\tABC\n\
+            r3\t4\t*\t0\t0\t*\t*\t0\t0\tGG\t*\n";
+        assert_eq!(
+            reads(SamIterator::new(sam.as_bytes())),
+            [
+                owned("r1", "ACGT", "IIII"),
+                owned("r2", "GTT", "CBA"),
+                owned("r3", "GG", "\"\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_sam() {
+        let cases = [
+            "r1\t4\t*\t0\n",
+            "r1\tx\t*\t0\t0\t*\t*\t0\t0\tAC\tII\n",
+            "r1\t4\t*\t0\t0\t*\t*\t0\t0\tAC\tI\n",
+        ];
+        for sam in cases {
+            let results: Vec<_> = SamIterator::new(sam.as_bytes()).collect();
+            assert!(matches!(results[..], [Err(_)]), "{sam:?} was accepted");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use core::fmt;
+
+use clap::ValueEnum;
+
+use crate::{
+    command_line::{MergeArgs, ScanArgs}, fasta_parsing::SeqRecord, fasterdust::fasterdust, slowdust::{
+        best_intervals, greedy_intervals, maximal_intervals, merge_intervals, slowdust, LCR,
+    },
+    slowdust2::slowdust2,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    Slowdust,
+    Slowdust2,
+    Fasterdust,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::Slowdust => "slowdust",
+            Algorithm::Slowdust2 => "slowdust2",
+            Algorithm::Fasterdust => "fasterdust",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeStrategy {
+    /// Union of overlapping intervals, which can fuse neighbouring repeats
+    Union,
+    /// Non-overlapping intervals with the largest total score
+    Best,
+    /// Non-overlapping intervals, taken from the highest score down
+    Greedy,
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MergeStrategy::Union => "union",
+            MergeStrategy::Best => "best",
+            MergeStrategy::Greedy => "greedy",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Bases scored at a time for records that are not stored in one piece
+pub const CHUNK_LEN: usize = 1 << 20;
+
+/// Calls `f(from, own_start, bases)` for successive chunks of `record`, where `bases`
+/// starts at `from`. Each chunk after the first also holds the `overlap` bases before
+/// `own_start`, which were passed with the previous chunk. A contiguous record is one chunk.
+pub fn for_each_chunk(record: &dyn SeqRecord, overlap: usize, mut f: impl FnMut(usize, usize, &str)) {
+    if record.is_contiguous() {
+        f(0, 0, record.get_sequence());
+        return;
+    }
+    let len = record.sequence_len();
+    for own_start in (0..len).step_by(CHUNK_LEN) {
+        let from = own_start.saturating_sub(overlap);
+        f(from, own_start, &record.bases(from, len.min(own_start + CHUNK_LEN)));
+    }
+}
+
+/// Bases of a record, scored on their own
+struct Chunk<'a> {
+    name: &'a str,
+    bases: &'a str,
+}
+
+impl SeqRecord for Chunk<'_> {
+    fn get_name(&self) -> &str {
+        self.name
+    }
+    fn get_sequence(&self) -> &str {
+        self.bases
+    }
+}
+
+/// Every good window of `record` found by the chosen scorer, before merging.
+/// Windows are at most `window` bases long, so a record is scored in chunks that
+/// overlap by that much, and each window is kept from the chunk where it ends.
+pub fn scan(params: &ScanArgs, record: &dyn SeqRecord) -> Vec<LCR> {
+    let mut output = Vec::new();
+    let (k, window, threshold) = (params.kmer.k, params.window, params.kmer.threshold);
+    for_each_chunk(record, window, |from, own_start, bases| {
+        let chunk = Chunk { name: record.get_name(), bases };
+        let mut found = Vec::new();
+        match params.algorithm {
+            Algorithm::Slowdust => slowdust(&chunk, k, window, threshold, &mut found),
+            Algorithm::Slowdust2 => slowdust2(&chunk, k, window, threshold, &mut found),
+            Algorithm::Fasterdust => fasterdust(&chunk, k, window, threshold, &mut found),
+        }
+        output.extend(found.into_iter().filter(|lcr| from + lcr.end > own_start).map(|mut lcr| {
+            lcr.start += from;
+            lcr.end += from;
+            lcr
+        }));
+    });
+    output
+}
+
+/// Turns the good intervals of a record into the LCRs to report, sorted by start
+pub fn select_intervals(intervals: Vec<LCR>, args: &MergeArgs) -> Vec<LCR> {
+    if !args.no_merge {
+        return match args.merge {
+            MergeStrategy::Union => merge_intervals(intervals),
+            MergeStrategy::Best => best_intervals(intervals),
+            MergeStrategy::Greedy => greedy_intervals(intervals),
+        };
+    }
+    if args.maximal_only {
+        return maximal_intervals(intervals);
+    }
+    let mut intervals = intervals;
+    intervals.sort_by(|a, b| a.start.cmp(&b.start).then(a.end.cmp(&b.end)));
+    intervals
+}
@@ -0,0 +1,155 @@
+use clap::ValueEnum;
+use std::{collections::HashMap, io::{self, Write}};
+
+use crate::{fasta_parsing::SeqRecord, scan::for_each_chunk, slowdust::LCR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TrackFormat {
+    Bedgraph,
+    Wig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TrackMode {
+    /// Best score of any window ending at the base, including windows below the threshold
+    End,
+    /// Best score of any good window covering the base
+    Cover,
+}
+
+/// For each base, the best score of any window of at most `max_window` bases
+/// ending there. Bases without a full k-mer before them are NaN.
+pub fn best_score_ending(seq: &str, k: usize, max_window: usize, t: f64) -> Vec<f32> {
+    let mut track = vec![f32::NAN; seq.len()];
+
+    for end in k..=seq.len() {
+        let mut prev_score = 0.0;
+        let mut best = f64::NEG_INFINITY;
+        let mut kmer_counts: HashMap<&str, f64> = HashMap::new();
+
+        for win in k..=max_window.min(end) {
+            let start = end - win;
+            let entry = kmer_counts.entry(&seq[start..start + k]).or_insert(0.0);
+            *entry += 1.0;
+
+            prev_score += (*entry).ln() - t;
+            best = best.max(prev_score);
+        }
+        track[end - 1] = best as f32;
+    }
+    track
+}
+
+/// `best_score_ending` for a whole record, computed chunk by chunk for records
+/// that are not stored in one piece
+pub fn record_score_ending(record: &dyn SeqRecord, k: usize, max_window: usize, t: f64) -> Vec<f32> {
+    let mut track = Vec::with_capacity(record.sequence_len());
+    for_each_chunk(record, max_window, |from, own_start, bases| {
+        track.extend_from_slice(&best_score_ending(bases, k, max_window, t)[own_start - from..]);
+    });
+    track
+}
+
+/// For each base, the best score of the good windows in `intervals` that cover it.
+/// Uncovered bases are NaN. Intervals are filled best first, so each base is set once.
+pub fn max_covering_score(len: usize, intervals: &[LCR]) -> Vec<f32> {
+    let mut track = vec![f32::NAN; len];
+    // next_unset[i] is the first base at or after i that has not been filled yet
+    let mut next_unset: Vec<usize> = (0..=len).collect();
+
+    let mut by_score: Vec<&LCR> = intervals.iter().collect();
+    by_score.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    for lcr in by_score {
+        let end = lcr.end.min(len);
+        let mut i = find_unset(&mut next_unset, lcr.start.min(len));
+        while i < end {
+            track[i] = lcr.score as f32;
+            next_unset[i] = i + 1;
+            i = find_unset(&mut next_unset, i + 1);
+        }
+    }
+    track
+}
+
+fn find_unset(next_unset: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while next_unset[root] != root {
+        root = next_unset[root];
+    }
+    // Path compression
+    let mut curr = i;
+    while next_unset[curr] != root {
+        let next = next_unset[curr];
+        next_unset[curr] = root;
+        curr = next;
+    }
+    root
+}
+
+pub fn write_track<W: Write>(
+    writer: &mut W,
+    format: TrackFormat,
+    name: &str,
+    offset: usize,
+    track: &[f32],
+) -> io::Result<()> {
+    match format {
+        TrackFormat::Bedgraph => write_bedgraph(writer, name, offset, track),
+        TrackFormat::Wig => write_wig(writer, name, offset, track),
+    }
+}
+
+pub fn write_track_header<W: Write>(writer: &mut W, format: TrackFormat) -> io::Result<()> {
+    let track_type = match format {
+        TrackFormat::Bedgraph => "bedGraph",
+        TrackFormat::Wig => "wiggle_0",
+    };
+    writeln!(writer, "track type={track_type} name=lcr_score description=\"LCR window score\"")
+}
+
+/// Runs of equal score become one 0-based half-open bedGraph line; NaN bases are skipped.
+/// `offset` is the position of the track's first base in the record.
+fn write_bedgraph<W: Write>(
+    writer: &mut W,
+    name: &str,
+    offset: usize,
+    track: &[f32],
+) -> io::Result<()> {
+    let mut i = 0;
+    while i < track.len() {
+        let value = track[i];
+        if value.is_nan() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < track.len() && track[i] == value {
+            i += 1;
+        }
+        writeln!(writer, "{name}\t{}\t{}\t{value:.4}", start + offset, i + offset)?;
+    }
+    Ok(())
+}
+
+/// Every run of defined scores starts a new 1-based fixedStep block
+fn write_wig<W: Write>(
+    writer: &mut W,
+    name: &str,
+    offset: usize,
+    track: &[f32],
+) -> io::Result<()> {
+    let mut in_block = false;
+    for (i, value) in track.iter().enumerate() {
+        if value.is_nan() {
+            in_block = false;
+            continue;
+        }
+        if !in_block {
+            writeln!(writer, "fixedStep chrom={name} start={} step=1", i + offset + 1)?;
+            in_block = true;
+        }
+        writeln!(writer, "{value:.4}")?;
+    }
+    Ok(())
+}
@@ -1,19 +1,22 @@
 use core::fmt;
 use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
 use statrs::function::factorial::ln_factorial;
 
-use crate::fasta_parsing::Fasta;
+use crate::fasta_parsing::SeqRecord;
 
+/// An interval of a sequence, 0-based half-open (`start..end`) for every scorer
 #[derive(Clone)]
 pub struct LCR {
     pub name: String,
     pub start: usize,
     pub end: usize,
+    pub score: f64,
 }
 
 impl LCR {
-    pub fn new(name: String, start: usize, end: usize) -> Self {
-        Self { name, start, end }
+    pub fn new(name: String, start: usize, end: usize, score: f64) -> Self {
+        Self { name, start, end, score }
     }
 
     pub fn get_name(&self) -> &str {
@@ -25,6 +28,9 @@ impl LCR {
     pub fn get_end(&self) -> usize {
         self.end
     }
+    pub fn get_score(&self) -> f64 {
+        self.score
+    }
 }
 
 impl fmt::Display for LCR {
@@ -33,7 +39,7 @@ impl fmt::Display for LCR {
     }
 }
 
-pub fn slowdust(input: &Fasta, k: usize, max_window: usize, threshold: f64, output: &mut Vec<LCR>) {
+pub fn slowdust(input: &dyn SeqRecord, k: usize, max_window: usize, threshold: f64, output: &mut Vec<LCR>) {
     let seq = input.get_sequence();
     let name = input
         .get_name()
@@ -55,28 +61,32 @@ pub fn slowdust(input: &Fasta, k: usize, max_window: usize, threshold: f64, outp
                 continue;
             }
 
-            let mut is_good = true;
-            for j in 0..=window.len() {
-                let prefix = &window[..j];
-                let suffix = &window[j..];
-                if longdust_score(prefix, k, threshold) > window_score
-                    || longdust_score(suffix, k, threshold) > window_score
-                {
-                    is_good = false;
-                    break;
-                }
-            }
-            if is_good {
+            if is_good_window(window, k, threshold, window_score) {
                 output.push(LCR {
                     name: name.to_owned(),
                     start: i - w,
                     end: i,
+                    score: window_score,
                 });
             }
         }
     }
 }
 
+/// A window is good if no prefix or suffix scores higher than the whole window
+pub fn is_good_window(window: &str, k: usize, threshold: f64, window_score: f64) -> bool {
+    for j in 0..=window.len() {
+        let prefix = &window[..j];
+        let suffix = &window[j..];
+        if longdust_score(prefix, k, threshold) > window_score
+            || longdust_score(suffix, k, threshold) > window_score
+        {
+            return false;
+        }
+    }
+    true
+}
+
 pub fn longdust_score(x: &str, k: usize, threshold: f64) -> f64 {
     if x.len() < k {
         return 0.0;
@@ -112,8 +122,9 @@ pub fn merge_intervals(mut intervals: Vec<LCR>) -> Vec<LCR> {
 
     for next in intervals.into_iter().skip(1) {
         if next.name == current.name && next.start <= current.end {
-            // same name and overlapping → merge
+            // same name and overlapping → merge, keeping the best window score
             current.end = current.end.max(next.end);
+            current.score = current.score.max(next.score);
         } else {
             // push previous and move on
             merged.push(current);
@@ -125,6 +136,140 @@ pub fn merge_intervals(mut intervals: Vec<LCR>) -> Vec<LCR> {
     merged
 }
 
+/// Keeps the intervals that are not contained in a higher-scoring interval of the
+/// same sequence, sorted by start. Runs in O(n log n) with a max Fenwick tree over the ranks of the ends.
+pub fn maximal_intervals(mut intervals: Vec<LCR>) -> Vec<LCR> {
+    // Anything that can contain an interval comes before it in this order
+    intervals.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then(a.start.cmp(&b.start))
+            .then(b.end.cmp(&a.end))
+            .then(b.score.total_cmp(&a.score))
+    });
+
+    let mut maximal = Vec::new();
+    let mut group_start = 0;
+    while group_start < intervals.len() {
+        let name = &intervals[group_start].name;
+        let group_end = group_start
+            + intervals[group_start..].partition_point(|lcr| &lcr.name == name);
+        let group = &intervals[group_start..group_end];
+        // Distinct ends, largest first, so a prefix of ranks is every end at or after an interval's
+        let mut ends: Vec<usize> = group.iter().map(|lcr| lcr.end).collect();
+        ends.sort_unstable_by(|a, b| b.cmp(a));
+        ends.dedup();
+        let rank = |end: usize| ends.partition_point(|&e| e > end) + 1;
+
+        // best[rank] is the best score of the intervals seen so far, as a max Fenwick
+        // tree over end ranks indexed from 1
+        let mut best = vec![f64::NEG_INFINITY; ends.len() + 1];
+        for lcr in group {
+            let mut i = rank(lcr.end);
+            let mut containing = f64::NEG_INFINITY;
+            while i > 0 {
+                containing = containing.max(best[i]);
+                i &= i - 1;
+            }
+            if containing <= lcr.score {
+                maximal.push(lcr.clone());
+            }
+            let mut i = rank(lcr.end);
+            while i < best.len() {
+                best[i] = best[i].max(lcr.score);
+                i += i & i.wrapping_neg();
+            }
+        }
+        group_start = group_end;
+    }
+    maximal
+}
+
+/// Picks non-overlapping intervals with the largest total score, by weighted
+/// interval scheduling. Intervals that only touch do not overlap. Sorted by start.
+pub fn best_intervals(mut intervals: Vec<LCR>) -> Vec<LCR> {
+    intervals.sort_by(|a, b| a.name.cmp(&b.name).then(a.end.cmp(&b.end)));
+
+    let mut chosen = Vec::new();
+    for group in intervals.chunk_by(|a, b| a.name == b.name) {
+        // total[i] is the best total using the first i intervals, by end
+        let mut total = vec![0.0f64; group.len() + 1];
+        // Number of intervals ending at or before the start of interval i
+        let before: Vec<usize> = group
+            .iter()
+            .map(|lcr| group.partition_point(|prev| prev.end <= lcr.start))
+            .collect();
+        for (i, lcr) in group.iter().enumerate() {
+            total[i + 1] = total[i].max(total[before[i]] + lcr.score);
+        }
+
+        let mut i = group.len();
+        let first = chosen.len();
+        while i > 0 {
+            if total[i] == total[i - 1] {
+                i -= 1;
+            } else {
+                chosen.push(group[i - 1].clone());
+                i = before[i - 1];
+            }
+        }
+        chosen[first..].reverse();
+    }
+    chosen
+}
+
+/// Takes intervals from the highest score down, skipping any that overlap one
+/// already taken. Sorted by start.
+pub fn greedy_intervals(mut intervals: Vec<LCR>) -> Vec<LCR> {
+    intervals.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // Chosen intervals of each sequence, by start
+    let mut taken: FxHashMap<String, BTreeMap<usize, usize>> = FxHashMap::default();
+    let mut chosen = Vec::new();
+    for lcr in intervals {
+        let spans = taken.entry(lcr.name.clone()).or_default();
+        let overlaps_before = spans
+            .range(..lcr.end)
+            .next_back()
+            .is_some_and(|(_, &end)| end > lcr.start);
+        if !overlaps_before {
+            spans.insert(lcr.start, lcr.end);
+            chosen.push(lcr);
+        }
+    }
+    chosen.sort_by(|a, b| a.name.cmp(&b.name).then(a.start.cmp(&b.start)));
+    chosen
+}
+
+/// Number of bases covered by at least one interval. Intervals may overlap.
+pub fn covered_bases(intervals: &[LCR]) -> usize {
+    let mut spans: Vec<(&str, usize, usize)> = intervals
+        .iter()
+        .map(|lcr| (lcr.name.as_str(), lcr.start, lcr.end))
+        .collect();
+    spans.sort_unstable();
+
+    let mut covered = 0;
+    let mut current: Option<(&str, usize, usize)> = None;
+    for (name, start, end) in spans {
+        match &mut current {
+            Some((curr_name, _, curr_end)) if *curr_name == name && start <= *curr_end => {
+                *curr_end = (*curr_end).max(end);
+            }
+            _ => {
+                if let Some((_, curr_start, curr_end)) = current {
+                    covered += curr_end - curr_start;
+                }
+                current = Some((name, start, end));
+            }
+        }
+    }
+    if let Some((_, curr_start, curr_end)) = current {
+        covered += curr_end - curr_start;
+    }
+    covered
+}
+
 /*
 pub fn slowdust2(input: Fasta, window_len: usize, threshold: f64, output: &mut Vec<LCR>){
     let seq = input.get_sequence();
@@ -209,3 +354,127 @@ fn score(s: &str, f_cache: Option<&mut HashMap<u64, f64>>) -> f64 {
     log_fact_sum
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lcr(name: &str, start: usize, end: usize, score: f64) -> LCR {
+        LCR::new(name.to_owned(), start, end, score)
+    }
+
+    fn spans(intervals: &[LCR]) -> Vec<(String, usize, usize, f64)> {
+        let mut spans: Vec<_> = intervals
+            .iter()
+            .map(|lcr| (lcr.name.clone(), lcr.start, lcr.end, lcr.score))
+            .collect();
+        spans.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        spans
+    }
+
+    /// Keeps an interval unless another one contains it with a higher score, in O(n^2)
+    fn brute_force_maximal(intervals: &[LCR]) -> Vec<LCR> {
+        intervals
+            .iter()
+            .filter(|lcr| {
+                !intervals.iter().any(|other| {
+                    other.name == lcr.name
+                        && other.start <= lcr.start
+                        && other.end >= lcr.end
+                        && other.score > lcr.score
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn maximal_intervals_handles_nested_equal_and_duplicate() {
+        let intervals = vec![
+            // Nested: the inner one scores lower and is dropped, the innermost scores higher and is kept
+            lcr("a", 0, 100, 5.0),
+            lcr("a", 10, 50, 3.0),
+            lcr("a", 20, 30, 8.0),
+            // Equal scores: a contained interval is not beaten by an equal-scoring one
+            lcr("a", 200, 300, 4.0),
+            lcr("a", 220, 280, 4.0),
+            // Duplicates are all kept
+            lcr("a", 400, 410, 2.0),
+            lcr("a", 400, 410, 2.0),
+            // Same coordinates on another sequence do not contain each other
+            lcr("b", 10, 50, 3.0),
+        ];
+        let maximal = maximal_intervals(intervals.clone());
+        assert_eq!(spans(&maximal), spans(&brute_force_maximal(&intervals)));
+        assert_eq!(maximal.len(), 7);
+        assert!(maximal.windows(2).all(|w| (&w[0].name, w[0].start) <= (&w[1].name, w[1].start)));
+    }
+
+    #[test]
+    fn maximal_intervals_matches_brute_force() {
+        // Small coordinates and scores so that nesting, ties and duplicates are common
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound) as usize
+        };
+        for _ in 0..200 {
+            let intervals: Vec<LCR> = (0..next(40))
+                .map(|_| {
+                    let start = next(30);
+                    let end = start + 1 + next(15);
+                    let name = if next(4) == 0 { "b" } else { "a" };
+                    lcr(name, start, end, next(5) as f64)
+                })
+                .collect();
+            assert_eq!(
+                spans(&maximal_intervals(intervals.clone())),
+                spans(&brute_force_maximal(&intervals))
+            );
+        }
+    }
+
+    #[test]
+    fn best_and_greedy_let_touching_intervals_through() {
+        let intervals = vec![lcr("a", 0, 10, 1.0), lcr("a", 10, 20, 1.0), lcr("a", 15, 25, 1.5)];
+        let touching = [("a".to_owned(), 0, 10, 1.0), ("a".to_owned(), 15, 25, 1.5)];
+        // 10..20 touches 0..10 but overlaps 15..25, which scores more
+        assert_eq!(spans(&best_intervals(intervals.clone())), touching);
+        assert_eq!(spans(&greedy_intervals(intervals)), touching);
+
+        let only_touching = vec![lcr("a", 0, 10, 1.0), lcr("a", 10, 20, 1.0)];
+        assert_eq!(best_intervals(only_touching.clone()).len(), 2);
+        assert_eq!(greedy_intervals(only_touching).len(), 2);
+    }
+
+    #[test]
+    fn best_and_greedy_keep_names_apart() {
+        let intervals = vec![
+            lcr("b", 0, 10, 2.0),
+            lcr("a", 5, 15, 1.0),
+            lcr("a", 0, 10, 3.0),
+            lcr("b", 5, 15, 1.0),
+        ];
+        let expected = [("a".to_owned(), 0, 10, 3.0), ("b".to_owned(), 0, 10, 2.0)];
+        let best = best_intervals(intervals.clone());
+        let greedy = greedy_intervals(intervals);
+        assert_eq!(spans(&best), expected);
+        assert_eq!(spans(&greedy), expected);
+        // Sorted by name, then start
+        assert_eq!((best[0].get_name(), best[1].get_name()), ("a", "b"));
+        assert_eq!((greedy[0].get_name(), greedy[1].get_name()), ("a", "b"));
+    }
+
+    #[test]
+    fn best_beats_greedy_on_one_interval_overlapping_two() {
+        // The middle interval outscores either neighbour, but not both together
+        let intervals = vec![lcr("a", 0, 10, 2.0), lcr("a", 5, 25, 3.0), lcr("a", 20, 30, 2.0)];
+        assert_eq!(
+            spans(&best_intervals(intervals.clone())),
+            [("a".to_owned(), 0, 10, 2.0), ("a".to_owned(), 20, 30, 2.0)]
+        );
+        assert_eq!(spans(&greedy_intervals(intervals)), [("a".to_owned(), 5, 25, 3.0)]);
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 
-use crate::fasta_parsing::Fasta;
+use crate::fasta_parsing::SeqRecord;
 use crate::slowdust::LCR;
 
-pub fn slowdust2(input: &Fasta, k: usize, max_window: usize, t: f64, output: &mut Vec<LCR>) {
+pub fn slowdust2(input: &dyn SeqRecord, k: usize, max_window: usize, t: f64, output: &mut Vec<LCR>) {
     let seq = input.get_sequence();
 
     for end in k..=seq.len() {
@@ -44,6 +44,7 @@ pub fn slowdust2(input: &Fasta, k: usize, max_window: usize, t: f64, output: &mu
                         .to_owned(),
                     start,
                     end,
+                    score: window_score,
                 })
             }  
         }
@@ -0,0 +1,194 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::{io::Write, time::Duration};
+
+use crate::{
+    classify::LcrClass,
+    command_line::{MergeArgs, ScanArgs},
+    fasta_parsing::SeqRecord,
+    output::{open_output, OutputCompression},
+    scan::for_each_chunk,
+    slowdust::{covered_bases, LCR},
+};
+
+/// Per-record statistics for the `--summary` report
+#[derive(Debug, Clone)]
+pub struct RecordSummary {
+    pub name: String,
+    pub length: usize,
+    pub lcrs: usize,
+    pub masked_bp: usize,
+    pub longest_lcr: usize,
+    pub n_bases: usize,
+    pub wall_time: Duration,
+}
+
+impl RecordSummary {
+    /// `lcrs` are the LCRs written for the record. Overlapping LCRs mask their shared bases once.
+    pub fn new(name: &str, record: &dyn SeqRecord, lcrs: &[LCR], wall_time: Duration) -> Self {
+        let lengths = lcrs.iter().map(|lcr| lcr.get_end() - lcr.get_start());
+        let mut n_bases = 0;
+        for_each_chunk(record, 0, |_, _, bases| {
+            n_bases += bases.bytes().filter(|b| b.eq_ignore_ascii_case(&b'N')).count();
+        });
+        Self {
+            name: name.to_owned(),
+            length: record.sequence_len(),
+            lcrs: lcrs.len(),
+            masked_bp: covered_bases(lcrs),
+            longest_lcr: lengths.max().unwrap_or(0),
+            n_bases,
+            wall_time,
+        }
+    }
+
+    /// Adds up the records of a run. The name is `total` and the wall time is the sum of the records.
+    pub fn total(records: &[RecordSummary]) -> Self {
+        let mut total = Self {
+            name: "total".to_owned(),
+            length: 0,
+            lcrs: 0,
+            masked_bp: 0,
+            longest_lcr: 0,
+            n_bases: 0,
+            wall_time: Duration::ZERO,
+        };
+        for record in records {
+            total.length += record.length;
+            total.lcrs += record.lcrs;
+            total.masked_bp += record.masked_bp;
+            total.longest_lcr = total.longest_lcr.max(record.longest_lcr);
+            total.n_bases += record.n_bases;
+            total.wall_time += record.wall_time;
+        }
+        total
+    }
+
+    pub fn masked_fraction(&self) -> f64 {
+        fraction(self.masked_bp, self.length)
+    }
+
+    pub fn n_content(&self) -> f64 {
+        fraction(self.n_bases, self.length)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "length": self.length,
+            "lcrs": self.lcrs,
+            "masked_bp": self.masked_bp,
+            "masked_fraction": self.masked_fraction(),
+            "longest_lcr": self.longest_lcr,
+            "n_content": self.n_content(),
+            "wall_time": self.wall_time.as_secs_f64(),
+        })
+    }
+}
+
+/// Everything that decides which LCRs a run reports
+pub struct RunParams<'a> {
+    pub scan: &'a ScanArgs,
+    pub merge: &'a MergeArgs,
+    /// Classes kept, or every class if empty
+    pub classes: &'a [LcrClass],
+    /// Regions scanned, or whole records if empty
+    pub regions: &'a [String],
+}
+
+impl RunParams<'_> {
+    /// The merge strategy, or `none` for `--no-merge`
+    fn merge_name(&self) -> String {
+        if self.merge.no_merge {
+            "none".to_owned()
+        } else {
+            self.merge.merge.to_string()
+        }
+    }
+
+    fn class_names(&self) -> Vec<String> {
+        self.classes.iter().map(LcrClass::to_string).collect()
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "algorithm": self.scan.algorithm.to_string(),
+            "k": self.scan.kmer.k,
+            "window": self.scan.window,
+            "threshold": self.scan.kmer.threshold,
+            "merge": self.merge_name(),
+            "maximal_only": self.merge.maximal_only,
+            "classes": self.class_names(),
+            "regions": self.regions,
+        })
+    }
+}
+
+fn fraction(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Writes the report as JSON if `path` ends in `.json`, and as TSV otherwise.
+/// Empty class and region lists are written as `all` in the TSV header.
+/// `records` should be in input order; `run_time` is the wall time of the whole run.
+pub fn write_summary(
+    path: &str,
+    compression: OutputCompression,
+    params: &RunParams,
+    records: &[RecordSummary],
+    run_time: Duration,
+) -> Result<()> {
+    let total = RecordSummary::total(records);
+    let mut writer = open_output(path, compression)?;
+    let is_json = path.trim_end_matches(".gz").trim_end_matches(".bgz").ends_with(".json");
+
+    if is_json {
+        let report = json!({
+            "parameters": params.to_json(),
+            "records": records.iter().map(RecordSummary::to_json).collect::<Vec<_>>(),
+            "total": total.to_json(),
+            "run_time": run_time.as_secs_f64(),
+        });
+        serde_json::to_writer_pretty(&mut writer, &report)?;
+        writeln!(writer)?;
+    } else {
+        let list = |items: &[String]| match items {
+            [] => "all".to_owned(),
+            items => items.join(","),
+        };
+        writeln!(
+            writer,
+            "#algorithm={} k={} window={} threshold={} merge={} maximal_only={} classes={} regions={} run_time={:.3}",
+            params.scan.algorithm,
+            params.scan.kmer.k,
+            params.scan.window,
+            params.scan.kmer.threshold,
+            params.merge_name(),
+            params.merge.maximal_only,
+            list(&params.class_names()),
+            list(params.regions),
+            run_time.as_secs_f64()
+        )?;
+        writeln!(writer, "Name\tLength\tLCRs\tMaskedBp\tMaskedFraction\tLongestLCR\tNContent\tWallTime")?;
+        for record in records.iter().chain([&total]) {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{:.6}\t{}\t{:.6}\t{:.3}",
+                record.name,
+                record.length,
+                record.lcrs,
+                record.masked_bp,
+                record.masked_fraction(),
+                record.longest_lcr,
+                record.n_content(),
+                record.wall_time.as_secs_f64()
+            )?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Result};
+use rustc_hash::FxHashMap;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    vec,
+};
+
+use crate::{fasta_parsing::Fasta, region::Region};
+
+pub const TWOBIT_SIGNATURE: u32 = 0x1A41_2743;
+
+/// Bases in 2-bit order: T=0, C=1, A=2, G=3
+const TWOBIT_BASES: [u8; 4] = [b'T', b'C', b'A', b'G'];
+
+pub fn is_twobit(path: &str) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    let read = File::open(path)?.read(&mut magic)?;
+    Ok(read == 4
+        && (u32::from_le_bytes(magic) == TWOBIT_SIGNATURE
+            || u32::from_be_bytes(magic) == TWOBIT_SIGNATURE))
+}
+
+/// A decoded `.2bit` record. Masked bases are lowercase and N-blocks are Ns.
+#[derive(Debug, Clone)]
+pub struct TwoBitRecord {
+    pub name: String,
+    pub sequence: String,
+    /// Runs of N, 0-based half-open
+    pub n_blocks: Vec<(usize, usize)>,
+    /// Soft-masked runs, 0-based half-open
+    pub mask_blocks: Vec<(usize, usize)>,
+}
+
+impl TwoBitRecord {
+    pub fn get_n_blocks(&self) -> &[(usize, usize)] {
+        &self.n_blocks
+    }
+    pub fn get_mask_blocks(&self) -> &[(usize, usize)] {
+        &self.mask_blocks
+    }
+    pub fn to_fasta(self) -> Fasta {
+        Fasta::new(self.name, self.sequence)
+    }
+}
+
+/// Layout of one record, read from its header
+#[derive(Debug, Clone)]
+struct RecordHeader {
+    dna_size: usize,
+    n_blocks: Vec<(usize, usize)>,
+    mask_blocks: Vec<(usize, usize)>,
+    /// File offset of the packed bases
+    dna_offset: u64,
+}
+
+/// Reader for UCSC `.2bit` files, with random access to records and regions by name
+pub struct TwoBitReader<R: Read + Seek> {
+    reader: R,
+    big_endian: bool,
+    names: Vec<String>,
+    offsets: FxHashMap<String, u64>,
+}
+
+impl TwoBitReader<BufReader<File>> {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> TwoBitReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let big_endian = if u32::from_le_bytes(magic) == TWOBIT_SIGNATURE {
+            false
+        } else if u32::from_be_bytes(magic) == TWOBIT_SIGNATURE {
+            true
+        } else {
+            return Err(anyhow!("Invalid 2bit format: bad signature"));
+        };
+
+        let mut twobit = Self {
+            reader,
+            big_endian,
+            names: Vec::new(),
+            offsets: FxHashMap::default(),
+        };
+        let version = twobit.read_u32()?;
+        if version > 1 {
+            return Err(anyhow!("Unsupported 2bit version {version}"));
+        }
+        let count = twobit.read_u32()?;
+        let _reserved = twobit.read_u32()?;
+
+        for _ in 0..count {
+            let mut name_len = [0u8; 1];
+            twobit.reader.read_exact(&mut name_len)?;
+            let mut name = vec![0u8; name_len[0] as usize];
+            twobit.reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|err| anyhow!("Invalid 2bit name: {err}"))?;
+            // Version 1 files use 64-bit offsets
+            let offset = if version == 1 {
+                twobit.read_u64()?
+            } else {
+                twobit.read_u32()? as u64
+            };
+            twobit.offsets.insert(name.clone(), offset);
+            twobit.names.push(name);
+        }
+        Ok(twobit)
+    }
+
+    pub fn get_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Length of record `name`, without reading its bases
+    pub fn length(&mut self, name: &str) -> Result<usize> {
+        Ok(self.read_header(name)?.dna_size)
+    }
+
+    pub fn read_record(&mut self, name: &str) -> Result<TwoBitRecord> {
+        let header = self.read_header(name)?;
+        let sequence = self.decode(&header, 0, header.dna_size)?;
+        Ok(TwoBitRecord {
+            name: name.to_owned(),
+            sequence,
+            n_blocks: header.n_blocks,
+            mask_blocks: header.mask_blocks,
+        })
+    }
+
+    /// Decodes bases `start..end` (0-based half-open) of record `name`, reading
+    /// only the packed bytes that cover them. `end` is clamped to the record length.
+    pub fn fetch(&mut self, name: &str, start: usize, end: usize) -> Result<String> {
+        let header = self.read_header(name)?;
+        self.decode(&header, start, end.min(header.dna_size))
+    }
+
+    /// Reads a region, checking that it lies within its record
+    pub fn fetch_region(&mut self, region: &Region) -> Result<String> {
+        let header = self.read_header(region.get_name())?;
+        let end = region.end_within(header.dna_size);
+        if region.get_start() >= end {
+            return Err(anyhow!(
+                "Region {region} is outside of {} ({} bp)",
+                region.get_name(),
+                header.dna_size
+            ));
+        }
+        self.decode(&header, region.get_start(), end)
+    }
+
+    /// Iterates over every record in file order
+    pub fn records(self) -> TwoBitIterator<R> {
+        TwoBitIterator {
+            names: self.names.clone().into_iter(),
+            reader: self,
+        }
+    }
+
+    fn read_header(&mut self, name: &str) -> Result<RecordHeader> {
+        let offset = *self
+            .offsets
+            .get(name)
+            .ok_or_else(|| anyhow!("Sequence {name} not found in 2bit file"))?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        let dna_size = self.read_u32()? as usize;
+        let n_blocks = self.read_blocks(name, dna_size)?;
+        let mask_blocks = self.read_blocks(name, dna_size)?;
+        let _reserved = self.read_u32()?;
+        let dna_offset = self.reader.stream_position()?;
+
+        Ok(RecordHeader {
+            dna_size,
+            n_blocks,
+            mask_blocks,
+            dna_offset,
+        })
+    }
+
+    /// Reads a block count, then all starts, then all sizes. Blocks are disjoint runs
+    /// of bases, so a corrupt count is caught before anything is allocated for it.
+    fn read_blocks(&mut self, name: &str, dna_size: usize) -> Result<Vec<(usize, usize)>> {
+        let count = self.read_u32()? as usize;
+        if count > dna_size {
+            return Err(anyhow!(
+                "Invalid 2bit format: {count} blocks in {name}, which has {dna_size} bases"
+            ));
+        }
+        let starts = (0..count)
+            .map(|_| self.read_u32().map(|v| v as usize))
+            .collect::<Result<Vec<_>>>()?;
+        let mut blocks = Vec::with_capacity(count);
+        for start in starts {
+            let size = self.read_u32()? as usize;
+            blocks.push((start, start + size));
+        }
+        Ok(blocks)
+    }
+
+    fn decode(&mut self, header: &RecordHeader, start: usize, end: usize) -> Result<String> {
+        if start >= end {
+            return Ok(String::new());
+        }
+        let first_byte = start / 4;
+        let last_byte = (end - 1) / 4;
+        let mut packed = vec![0u8; last_byte - first_byte + 1];
+        self.reader
+            .seek(SeekFrom::Start(header.dna_offset + first_byte as u64))?;
+        self.reader.read_exact(&mut packed)?;
+
+        let mut seq: Vec<u8> = (start..end)
+            .map(|pos| {
+                let byte = packed[pos / 4 - first_byte];
+                let shift = 6 - 2 * (pos % 4);
+                TWOBIT_BASES[((byte >> shift) & 3) as usize]
+            })
+            .collect();
+
+        for &(block_start, block_end) in &header.n_blocks {
+            for base in &mut seq[overlap(block_start, block_end, start, end)] {
+                *base = b'N';
+            }
+        }
+        for &(block_start, block_end) in &header.mask_blocks {
+            for base in &mut seq[overlap(block_start, block_end, start, end)] {
+                *base = base.to_ascii_lowercase();
+            }
+        }
+        // Only ACGTN and their lowercase forms are produced
+        Ok(String::from_utf8(seq).expect("2bit bases are ASCII"))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0u8; 8];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// Part of block `block_start..block_end` inside `start..end`, relative to `start`
+fn overlap(block_start: usize, block_end: usize, start: usize, end: usize) -> Range<usize> {
+    let from = block_start.clamp(start, end);
+    let to = block_end.clamp(start, end);
+    from - start..to - start
+}
+
+pub struct TwoBitIterator<R: Read + Seek> {
+    reader: TwoBitReader<R>,
+    names: vec::IntoIter<String>,
+}
+
+impl<R: Read + Seek> Iterator for TwoBitIterator<R> {
+    type Item = Result<TwoBitRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let name = self.names.next()?;
+        Some(self.reader.read_record(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Name, bases, N-blocks and mask blocks, with blocks as 0-based half-open ranges
+    type TestRecord<'a> = (&'a str, &'a str, &'a [(u32, u32)], &'a [(u32, u32)]);
+
+    /// Encodes a version 0 `.2bit` file with the given byte order
+    fn encode(records: &[TestRecord], big_endian: bool) -> Vec<u8> {
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let mut file = Vec::new();
+        for v in [TWOBIT_SIGNATURE, 0, records.len() as u32, 0] {
+            file.extend(u32_bytes(v));
+        }
+        let index_len: usize = records.iter().map(|(name, ..)| 1 + name.len() + 4).sum();
+        let mut data = Vec::new();
+        for (name, seq, n_blocks, mask_blocks) in records {
+            file.push(name.len() as u8);
+            file.extend(name.as_bytes());
+            file.extend(u32_bytes((16 + index_len + data.len()) as u32));
+
+            data.extend(u32_bytes(seq.len() as u32));
+            for blocks in [n_blocks, mask_blocks] {
+                data.extend(u32_bytes(blocks.len() as u32));
+                data.extend(blocks.iter().flat_map(|&(start, _)| u32_bytes(start)));
+                data.extend(blocks.iter().flat_map(|&(start, end)| u32_bytes(end - start)));
+            }
+            data.extend(u32_bytes(0));
+            for chunk in seq.as_bytes().chunks(4) {
+                let mut byte = 0u8;
+                for (i, base) in chunk.iter().enumerate() {
+                    let code = TWOBIT_BASES.iter().position(|b| b == base).unwrap_or(0);
+                    byte |= (code as u8) << (6 - 2 * i);
+                }
+                data.push(byte);
+            }
+        }
+        file.extend(data);
+        file
+    }
+
+    fn sample(big_endian: bool) -> TwoBitReader<Cursor<Vec<u8>>> {
+        let records: &[TestRecord] = &[
+            ("chr1", "ACGTACGTAC", &[(2, 4)], &[(5, 8)]),
+            ("chr2", "GGGTT", &[], &[(0, 5)]),
+        ];
+        TwoBitReader::new(Cursor::new(encode(records, big_endian))).unwrap()
+    }
+
+    #[test]
+    fn decodes_n_blocks_and_masks() {
+        for big_endian in [false, true] {
+            let mut twobit = sample(big_endian);
+            assert_eq!(twobit.get_names(), ["chr1", "chr2"]);
+            let chr1 = twobit.read_record("chr1").unwrap();
+            assert_eq!(chr1.sequence, "ACNNAcgtAC");
+            assert_eq!(chr1.get_n_blocks(), [(2, 4)]);
+            assert_eq!(chr1.get_mask_blocks(), [(5, 8)]);
+            assert_eq!(twobit.read_record("chr2").unwrap().sequence, "gggtt");
+        }
+    }
+
+    #[test]
+    fn fetches_regions_across_blocks() {
+        let mut twobit = sample(false);
+        assert_eq!(twobit.fetch("chr1", 3, 7).unwrap(), "NAcg");
+        assert_eq!(twobit.fetch("chr1", 7, 100).unwrap(), "tAC");
+        assert_eq!(twobit.length("chr2").unwrap(), 5);
+        assert!(twobit.fetch("chr3", 0, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_block_count() {
+        let records: &[TestRecord] = &[("chr1", "ACGT", &[], &[])];
+        let mut file = encode(records, false);
+        // The N-block count follows the record's dna size, after the header and index
+        let count_at = 16 + (1 + 4 + 4) + 4;
+        file[count_at..count_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut twobit = TwoBitReader::new(Cursor::new(file)).unwrap();
+        assert!(twobit.read_record("chr1").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        assert!(TwoBitReader::new(Cursor::new(vec![0u8; 16])).is_err());
+    }
+}